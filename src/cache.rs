@@ -0,0 +1,464 @@
+//! Binary cache of parsed locations, for instant reloads.
+//!
+//! The cache is written next to the source file as `<source>.cache`, and is only used while
+//! the source has the same size and modification time as when the cache was written.
+//!
+//! The layout is columnar: each field of `Location` is stored as its own run of fixed-size
+//! little-endian values, and `Activities` and `Activity` as their own tables, with the number
+//! of entries belonging to each row. Strings are stored once, in a table, and referred to by
+//! id, as the same few activity types and sources repeat throughout a file.
+
+use chrono::{DateTime, FixedOffset};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::{Activities, Activity, Location, Locations, Result};
+
+const MAGIC: &[u8] = b"LHCACHE\0";
+const VERSION: u32 = 1;
+
+// marks an absent optional value in id and count columns
+const NONE: u32 = u32::MAX;
+
+// getter and setter of an optional field
+type I32Field = (fn(&Location) -> Option<i32>, fn(&mut Location, Option<i32>));
+type StringField = (fn(&Location) -> Option<&String>, fn(&mut Location, Option<String>));
+
+// fields stored as a presence column followed by a value column
+const OPTIONAL_I32: [I32Field; 6] = [
+    (|l| l.accuracy, |l, v| l.accuracy = v),
+    (|l| l.altitude, |l, v| l.altitude = v),
+    (|l| l.velocity, |l, v| l.velocity = v),
+    (|l| l.heading, |l, v| l.heading = v),
+    (|l| l.vertical_accuracy, |l, v| l.vertical_accuracy = v),
+    (|l| l.os_level, |l, v| l.os_level = v),
+];
+
+// fields stored as a string id column
+const OPTIONAL_STRING: [StringField; 4] = [
+    (|l| l.source.as_ref(), |l, v| l.source = v),
+    (|l| l.platform_type.as_ref(), |l, v| l.platform_type = v),
+    (|l| l.form_factor.as_ref(), |l, v| l.form_factor = v),
+    (|l| l.place_id.as_ref(), |l, v| l.place_id = v),
+];
+
+/// where the cache for `source` is written, e.g. `Records.json.cache`
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut name = source.file_name().unwrap_or_default().to_os_string();
+    name.push(".cache");
+    source.with_file_name(name)
+}
+
+/// Reads the cache for `source`, if there is one, and it is still up to date.
+pub fn read_cache(source: &Path) -> Result<Option<Locations>> {
+    let data = match fs::read(cache_path(source)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut header = Column { data: &data[..] };
+    if header.bytes(MAGIC.len())? != MAGIC || header.u32()? != VERSION {
+        return Ok(None);
+    }
+    if header.bytes(SOURCE_KEY_SIZE)? != source_key(source)? {
+        return Ok(None);
+    }
+
+    decode(header.data).map(Some)
+}
+
+/// Writes the cache for `source`. The locations should be everything that was read from it.
+pub fn write_cache(source: &Path, locations: &[Location]) -> Result<()> {
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&source_key(source)?);
+    encode(locations, &mut data)?;
+
+    // write to a temporary file first, so a half-written cache is never read
+    let path = cache_path(source);
+    let mut partial = path.clone().into_os_string();
+    partial.push(".partial");
+    fs::write(&partial, data)?;
+    fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+const SOURCE_KEY_SIZE: usize = 20;
+
+// size and modification time of the source file, which change whenever it is replaced
+pub(crate) fn source_stamp(source: &Path) -> io::Result<(u64, u64, u32)> {
+    let metadata = fs::metadata(source)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+fn source_key(source: &Path) -> io::Result<Vec<u8>> {
+    let (size, secs, nanos) = source_stamp(source)?;
+
+    let mut key = Vec::with_capacity(SOURCE_KEY_SIZE);
+    key.extend_from_slice(&size.to_le_bytes());
+    key.extend_from_slice(&secs.to_le_bytes());
+    key.extend_from_slice(&nanos.to_le_bytes());
+    Ok(key)
+}
+
+#[derive(Default)]
+struct Strings {
+    ids: HashMap<String, u32>,
+    table: Vec<u8>,
+    count: u32,
+}
+
+impl Strings {
+    fn id(&mut self, s: Option<&str>) -> u32 {
+        let Some(s) = s else {
+            return NONE;
+        };
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+
+        let id = self.count;
+        self.count += 1;
+        self.table.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.table.extend_from_slice(s.as_bytes());
+        self.ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+// seconds, nanoseconds, and the offset in seconds
+const TIMESTAMP_SIZE: usize = 16;
+
+fn put_timestamp(column: &mut Vec<u8>, timestamp: &DateTime<FixedOffset>) {
+    column.extend_from_slice(&timestamp.timestamp().to_le_bytes());
+    column.extend_from_slice(&timestamp.timestamp_subsec_nanos().to_le_bytes());
+    column.extend_from_slice(&timestamp.offset().local_minus_utc().to_le_bytes());
+}
+
+// values which don't fit a fixed-size column are kept as JSON
+fn put_json<T: serde::Serialize>(column: &mut Vec<u8>, value: Option<&T>) -> io::Result<()> {
+    match value.map(serde_json::to_vec).transpose()? {
+        Some(json) => {
+            column.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            column.extend_from_slice(&json);
+        }
+        None => column.extend_from_slice(&NONE.to_le_bytes()),
+    }
+    Ok(())
+}
+
+fn encode(locations: &[Location], out: &mut Vec<u8>) -> io::Result<()> {
+    let mut strings = Strings::default();
+
+    let mut timestamps = Vec::new();
+    let mut latitudes = Vec::new();
+    let mut longitudes = Vec::new();
+    let mut optional_i32 = vec![(Vec::new(), Vec::new()); OPTIONAL_I32.len()];
+    let mut optional_string = vec![Vec::new(); OPTIONAL_STRING.len()];
+    let mut device_tags = (Vec::new(), Vec::new());
+    let mut battery_charging = Vec::new();
+    let mut optional_timestamps = [(Vec::new(), Vec::new()), (Vec::new(), Vec::new())];
+    let mut json = Vec::new();
+
+    // activities table, and the activity table within that
+    let mut activities_counts = Vec::new();
+    let mut activities_timestamps = Vec::new();
+    let mut activity_counts = Vec::new();
+    let mut activity_types = Vec::new();
+    let mut activity_confidences = Vec::new();
+
+    for location in locations {
+        put_timestamp(&mut timestamps, &location.timestamp);
+        latitudes.extend_from_slice(&location.latitude.to_le_bytes());
+        longitudes.extend_from_slice(&location.longitude.to_le_bytes());
+
+        for ((get, _), (present, values)) in OPTIONAL_I32.iter().zip(optional_i32.iter_mut()) {
+            let value = get(location);
+            present.push(value.is_some() as u8);
+            values.extend_from_slice(&value.unwrap_or_default().to_le_bytes());
+        }
+        for ((get, _), ids) in OPTIONAL_STRING.iter().zip(optional_string.iter_mut()) {
+            ids.extend_from_slice(&strings.id(get(location).map(String::as_str)).to_le_bytes());
+        }
+
+        device_tags.0.push(location.device_tag.is_some() as u8);
+        device_tags.1.extend_from_slice(&location.device_tag.unwrap_or_default().to_le_bytes());
+        battery_charging.push(match location.battery_charging {
+            Some(charging) => charging as u8,
+            None => 2,
+        });
+
+        let optional = [location.server_timestamp, location.device_timestamp];
+        for (timestamp, (present, values)) in optional.iter().zip(optional_timestamps.iter_mut()) {
+            present.push(timestamp.is_some() as u8);
+            put_timestamp(values, &timestamp.unwrap_or(location.timestamp));
+        }
+
+        put_json(&mut json, location.active_wifi_scan.as_ref())?;
+        put_json(&mut json, location.extra.as_ref())?;
+
+        match &location.activities {
+            Some(activities) => {
+                activities_counts.extend_from_slice(&(activities.len() as u32).to_le_bytes());
+                for a in activities {
+                    put_timestamp(&mut activities_timestamps, &a.timestamp);
+                    activity_counts.extend_from_slice(&(a.activities.len() as u32).to_le_bytes());
+                    for activity in &a.activities {
+                        activity_types.extend_from_slice(&strings.id(Some(activity.activity_type.as_str())).to_le_bytes());
+                        activity_confidences.extend_from_slice(&activity.confidence.to_le_bytes());
+                    }
+                }
+            }
+            None => activities_counts.extend_from_slice(&NONE.to_le_bytes()),
+        }
+    }
+
+    let mut columns = vec![timestamps, latitudes, longitudes];
+    for (present, values) in optional_i32 {
+        columns.push(present);
+        columns.push(values);
+    }
+    columns.extend(optional_string);
+    columns.push(device_tags.0);
+    columns.push(device_tags.1);
+    columns.push(battery_charging);
+    for (present, values) in optional_timestamps {
+        columns.push(present);
+        columns.push(values);
+    }
+    columns.push(json);
+    columns.push(activities_counts);
+    columns.push(activities_timestamps);
+    columns.push(activity_counts);
+    columns.push(activity_types);
+    columns.push(activity_confidences);
+    columns.push(strings.table);
+
+    out.extend_from_slice(&(locations.len() as u64).to_le_bytes());
+    for column in columns {
+        out.extend_from_slice(&(column.len() as u64).to_le_bytes());
+        out.extend_from_slice(&column);
+    }
+    Ok(())
+}
+
+// reads values from the front of a column
+struct Column<'a> {
+    data: &'a [u8],
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt location history cache")
+}
+
+impl<'a> Column<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(corrupt());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn timestamp(&mut self) -> io::Result<DateTime<FixedOffset>> {
+        let (secs, nanos, offset) = (self.i64()?, self.u32()?, self.i32()?);
+        let offset = FixedOffset::east_opt(offset).ok_or_else(corrupt)?;
+        Ok(DateTime::from_timestamp(secs, nanos).ok_or_else(corrupt)?.with_timezone(&offset))
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        match self.u32()? {
+            NONE => Ok(None),
+            len => serde_json::from_slice(self.bytes(len as usize)?).map(Some).map_err(|_| corrupt()),
+        }
+    }
+
+    // the next column in the file
+    fn column(&mut self) -> io::Result<Column<'a>> {
+        let len = self.u64()?;
+        Ok(Column {
+            data: self.bytes(usize::try_from(len).map_err(|_| corrupt())?)?,
+        })
+    }
+}
+
+fn string(strings: &[String], id: u32) -> io::Result<Option<&String>> {
+    match id {
+        NONE => Ok(None),
+        id => strings.get(id as usize).map(Some).ok_or_else(corrupt),
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Locations> {
+    let mut file = Column { data };
+    let count = usize::try_from(file.u64()?).map_err(|_| corrupt())?;
+
+    let mut timestamps = file.column()?;
+    // every location has a timestamp, so a count with more locations than timestamps is corrupt,
+    // and mustn't be used to allocate the locations
+    if timestamps.data.len() / TIMESTAMP_SIZE < count {
+        return Err(corrupt().into());
+    }
+    let mut latitudes = file.column()?;
+    let mut longitudes = file.column()?;
+    let mut optional_i32 = Vec::new();
+    for _ in OPTIONAL_I32 {
+        optional_i32.push((file.column()?, file.column()?));
+    }
+    let mut optional_string = Vec::new();
+    for _ in OPTIONAL_STRING {
+        optional_string.push(file.column()?);
+    }
+    let mut device_tags = (file.column()?, file.column()?);
+    let mut battery_charging = file.column()?;
+    let mut optional_timestamps = [(file.column()?, file.column()?), (file.column()?, file.column()?)];
+    let mut json = file.column()?;
+    let mut activities_counts = file.column()?;
+    let mut activities_timestamps = file.column()?;
+    let mut activity_counts = file.column()?;
+    let mut activity_types = file.column()?;
+    let mut activity_confidences = file.column()?;
+
+    let mut table = file.column()?;
+    let mut strings = Vec::new();
+    while !table.data.is_empty() {
+        let len = table.u32()? as usize;
+        strings.push(String::from_utf8(table.bytes(len)?.to_vec()).map_err(|_| corrupt())?);
+    }
+
+    let mut locations = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut location = Location {
+            timestamp: timestamps.timestamp()?,
+            latitude: latitudes.f64()?,
+            longitude: longitudes.f64()?,
+            ..Default::default()
+        };
+
+        for ((_, set), (present, values)) in OPTIONAL_I32.iter().zip(optional_i32.iter_mut()) {
+            let (present, value) = (present.u8()?, values.i32()?);
+            set(&mut location, (present == 1).then_some(value));
+        }
+        for ((_, set), ids) in OPTIONAL_STRING.iter().zip(optional_string.iter_mut()) {
+            set(&mut location, string(&strings, ids.u32()?)?.cloned());
+        }
+
+        let (present, device_tag) = (device_tags.0.u8()?, device_tags.1.i64()?);
+        location.device_tag = (present == 1).then_some(device_tag);
+        location.battery_charging = match battery_charging.u8()? {
+            2 => None,
+            charging => Some(charging == 1),
+        };
+
+        let mut optional = [None, None];
+        for (timestamp, (present, values)) in optional.iter_mut().zip(optional_timestamps.iter_mut()) {
+            let (present, value) = (present.u8()?, values.timestamp()?);
+            *timestamp = (present == 1).then_some(value);
+        }
+        [location.server_timestamp, location.device_timestamp] = optional;
+
+        location.active_wifi_scan = json.json()?;
+        location.extra = json.json()?;
+
+        location.activities = match activities_counts.u32()? {
+            NONE => None,
+            n => Some(
+                (0..n)
+                    .map(|_| {
+                        let timestamp = activities_timestamps.timestamp()?;
+                        let activities = (0..activity_counts.u32()?)
+                            .map(|_| {
+                                Ok(Activity {
+                                    activity_type: string(&strings, activity_types.u32()?)?.ok_or_else(corrupt)?.clone().into(),
+                                    confidence: activity_confidences.i32()?,
+                                })
+                            })
+                            .collect::<io::Result<Vec<Activity>>>()?;
+                        Ok(Activities { activities, timestamp })
+                    })
+                    .collect::<io::Result<Vec<Activities>>>()?,
+            ),
+        };
+
+        locations.push(location);
+    }
+
+    Ok(locations)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn cache_round_trip() {
+        let test_data = r#"{"locations": [{
+            "latitudeE7": -378224062, "longitudeE7": 1450703899, "accuracy": 12, "altitude": -3,
+            "activity": [{"activity": [{"type": "STILL", "confidence": 90}, {"type": "ON_FOOT", "confidence": 10}],
+                          "timestamp": "2024-01-01T09:11:58.123+10:00"}, {"activity": [], "timestamp": "2024-01-01T09:11:59Z"}],
+            "source": "WIFI", "deviceTag": -1412907366, "platformType": "ANDROID", "batteryCharging": false,
+            "serverTimestamp": "2024-01-01T09:12:01.5Z", "placeId": "ChIJ",
+            "activeWifiScan": {"accessPoints": [{"mac": "1", "strength": -50, "isConnected": true}]},
+            "timestamp": "2024-01-01T09:12:00.000000001Z"
+        }, {
+            "latitudeE7": -378100000, "longitudeE7": 1450800000, "source": "WIFI", "timestamp": "2024-01-01T09:13:00Z"
+        }]}"#;
+        let locations = crate::deserialize_lossless(test_data).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("location_history_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Records.json");
+        std::fs::write(&source, test_data).unwrap();
+
+        assert!(crate::read_cache(&source).unwrap().is_none());
+        crate::write_cache(&source, &locations).unwrap();
+        let cached = crate::read_cache(&source).unwrap().unwrap();
+        assert_eq!(format!("{:?}", cached), format!("{:?}", locations));
+
+        // a corrupt cache is an error, rather than a location count it doesn't have room for
+        let cache = crate::cache_path(&source);
+        let mut data = std::fs::read(&cache).unwrap();
+        data[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&cache, data).unwrap();
+        assert!(crate::read_cache(&source).is_err());
+
+        // a changed source invalidates the cache
+        std::fs::write(&source, test_data.replace("\"altitude\": -3", "\"altitude\": -30")).unwrap();
+        assert!(crate::read_cache(&source).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+//! Writing locations as Apache Parquet, for analytics tools such as DuckDB, Polars and pandas.
+//!
+//! Each location is a row, with a typed column per field. Timestamps are microseconds since
+//! the epoch, in UTC, with the offset of the timezone they were recorded in kept alongside
+//! in `utc_offset`. Activities keep the layout of Records.json, as a list of samples, each with
+//! a timestamp and a list of `{type, confidence}`, so e.g. in DuckDB:
+//!
+//! ```sql
+//! SELECT timestamp, unnest(activities[1].activity, recursive := true) FROM 'history.parquet';
+//! ```
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arrow_array::builder::{Float64Builder, Int32Builder, StringBuilder, TimestampMicrosecondBuilder};
+use arrow_array::{ArrayRef, Int32Array, ListArray, RecordBatch, StringArray, StructArray, TimestampMicrosecondArray};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::{Activities, Location, Result};
+
+// rows are converted this many at a time, to bound the memory used on top of the row group
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+}
+
+fn activity_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("type", DataType::Utf8, false),
+        Field::new("confidence", DataType::Int32, false),
+    ])
+}
+
+fn sample_fields() -> Fields {
+    let activity = Field::new_list_field(DataType::Struct(activity_fields()), false);
+    Fields::from(vec![
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new_list("activity", activity, false),
+    ])
+}
+
+/// the schema of the Parquet output
+pub fn parquet_schema() -> SchemaRef {
+    let sample = Field::new_list_field(DataType::Struct(sample_fields()), false);
+    Arc::new(Schema::new(vec![
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new("utc_offset", DataType::Int32, false),
+        Field::new("latitude", DataType::Float64, false),
+        Field::new("longitude", DataType::Float64, false),
+        Field::new("accuracy", DataType::Int32, true),
+        Field::new("altitude", DataType::Int32, true),
+        Field::new("velocity", DataType::Int32, true),
+        Field::new("heading", DataType::Int32, true),
+        Field::new("vertical_accuracy", DataType::Int32, true),
+        Field::new("source", DataType::Utf8, true),
+        Field::new_list("activities", sample, true),
+    ]))
+}
+
+/// Writes locations as a Parquet file, with the schema given by `parquet_schema`.
+pub fn write_parquet<W: Write + Send>(writer: W, locations: &[Location]) -> Result<()> {
+    let schema = parquet_schema();
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties)).map_err(io::Error::other)?;
+
+    for chunk in locations.chunks(BATCH_ROWS) {
+        let batch = RecordBatch::try_new(schema.clone(), columns(chunk)).map_err(io::Error::other)?;
+        writer.write(&batch).map_err(io::Error::other)?;
+    }
+
+    let mut inner = writer.into_inner().map_err(io::Error::other)?;
+    inner.flush()?;
+    Ok(())
+}
+
+fn columns(locations: &[Location]) -> Vec<ArrayRef> {
+    let int32 = |field: fn(&Location) -> Option<i32>| -> ArrayRef {
+        let mut builder = Int32Builder::with_capacity(locations.len());
+        locations.iter().for_each(|l| builder.append_option(field(l)));
+        Arc::new(builder.finish())
+    };
+
+    let mut timestamp = TimestampMicrosecondBuilder::with_capacity(locations.len()).with_timezone("UTC");
+    let mut latitude = Float64Builder::with_capacity(locations.len());
+    let mut longitude = Float64Builder::with_capacity(locations.len());
+    let mut source = StringBuilder::new();
+    for location in locations {
+        timestamp.append_value(location.timestamp.timestamp_micros());
+        latitude.append_value(location.latitude);
+        longitude.append_value(location.longitude);
+        source.append_option(location.source.as_deref());
+    }
+
+    vec![
+        Arc::new(timestamp.finish()),
+        int32(|l| Some(l.timestamp.offset().local_minus_utc())),
+        Arc::new(latitude.finish()),
+        Arc::new(longitude.finish()),
+        int32(|l| l.accuracy),
+        int32(|l| l.altitude),
+        int32(|l| l.velocity),
+        int32(|l| l.heading),
+        int32(|l| l.vertical_accuracy),
+        Arc::new(source.finish()),
+        Arc::new(activities(locations)),
+    ]
+}
+
+// the activities of each location, as a list of samples, each a list of activities
+fn activities(locations: &[Location]) -> ListArray {
+    let samples: Vec<&Activities> = locations.iter().flat_map(|l| l.activities.iter().flatten()).collect();
+    let activities = samples.iter().flat_map(|s| &s.activities);
+
+    let activity = StructArray::new(
+        activity_fields(),
+        vec![
+            Arc::new(activities.clone().map(|a| Some(a.activity_type.as_str())).collect::<StringArray>()),
+            Arc::new(activities.map(|a| Some(a.confidence)).collect::<Int32Array>()),
+        ],
+        None,
+    );
+    let activity_lists = ListArray::new(
+        Arc::new(Field::new_list_field(DataType::Struct(activity_fields()), false)),
+        OffsetBuffer::from_lengths(samples.iter().map(|s| s.activities.len())),
+        Arc::new(activity),
+        None,
+    );
+
+    let timestamps: TimestampMicrosecondArray =
+        samples.iter().map(|s| Some(s.timestamp.timestamp_micros())).collect();
+    let sample = StructArray::new(
+        sample_fields(),
+        vec![Arc::new(timestamps.with_timezone("UTC")), Arc::new(activity_lists)],
+        None,
+    );
+
+    // a location without any activities is null, rather than an empty list
+    let nulls = NullBuffer::from_iter(locations.iter().map(|l| l.activities.is_some()));
+    ListArray::new(
+        Arc::new(Field::new_list_field(DataType::Struct(sample_fields()), false)),
+        OffsetBuffer::from_lengths(locations.iter().map(|l| l.activities.as_ref().map_or(0, Vec::len))),
+        Arc::new(sample),
+        Some(nulls),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::Array;
+    use arrow_array::types::{Int32Type, TimestampMicrosecondType};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn parquet_columns() {
+        let locations = crate::fixtures::locations();
+
+        let mut out = Vec::new();
+        crate::write_parquet(&mut out, &locations).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(out)).unwrap();
+        assert_eq!(reader.schema(), &crate::parquet_schema());
+        let batch = reader.build().unwrap().next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+
+        let timestamps = batch["timestamp"].as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(timestamps.value(0), locations[0].timestamp.timestamp_micros());
+        assert_eq!(batch["utc_offset"].as_primitive::<Int32Type>().value(0), 36000);
+        assert!(batch["accuracy"].is_null(1));
+
+        let activities = batch["activities"].as_list::<i32>();
+        assert!(activities.is_null(1));
+        let samples = activities.value(0);
+        let samples = samples.as_struct();
+        assert_eq!(samples.len(), 1);
+        let activity = samples.column_by_name("activity").unwrap().as_list::<i32>().value(0);
+        let types = activity.as_struct().column_by_name("type").unwrap().as_string::<i32>();
+        assert_eq!((types.value(0), types.value(1)), ("WALKING", "STILL"));
+    }
+}
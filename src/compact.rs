@@ -0,0 +1,369 @@
+//! Compact in-memory representation of locations.
+//!
+//! A `Location` holds two `f64`s, a `DateTime<FixedOffset>`, a dozen optional fields and a
+//! `Vec<Activities>` per location, so several years of history take gigabytes.
+//! `CompactLocations` keeps E7 coordinates, millisecond timestamps, and activity types as
+//! `u8` ids with `u8` confidences, with the activities of every location in shared arenas.
+//!
+//! Only the timestamp, coordinates, accuracy, altitude and activities are kept. Timestamps
+//! are converted to UTC. Past 255 distinct activity types, the rest become `UNKNOWN`.
+
+use chrono::{DateTime, FixedOffset};
+use geo::{HaversineDistance, Point};
+use std::collections::HashSet;
+
+use crate::{to_e7, Activities, Activity, ActivityType, Location, Locations, LocationsExt};
+
+/// a location within `CompactLocations`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactLocation {
+    /// milliseconds since the unix epoch
+    pub timestamp_ms: i64,
+    pub latitude_e7: i32,
+    pub longitude_e7: i32,
+    /// accuracy of location sample in meters
+    pub accuracy: Option<i32>,
+    /// altitude in meters, if available
+    pub altitude: Option<i32>,
+    // first entry in `CompactLocations::samples`, and how many belong to this location
+    samples: Option<(u32, u16)>,
+}
+
+/// a sample of activities, within `CompactLocations`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactActivities {
+    /// milliseconds since the unix epoch
+    pub timestamp_ms: i64,
+    // first entry in `CompactLocations::activities`, and how many belong to this sample
+    activities: (u32, u16),
+}
+
+/// a single activity, within `CompactLocations`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactActivity {
+    // entry in `CompactLocations::types`
+    activity_type: u8,
+    pub confidence: u8,
+}
+
+/// locations, with their activities kept in shared arenas
+#[derive(Clone, Debug, Default)]
+pub struct CompactLocations {
+    pub locations: Vec<CompactLocation>,
+    samples: Vec<CompactActivities>,
+    activities: Vec<CompactActivity>,
+    // every activity type seen so far, in order of appearance
+    types: Vec<ActivityType>,
+}
+
+impl CompactLocation {
+    pub fn latitude(&self) -> f64 {
+        self.latitude_e7 as f64 / 10_000_000.0
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude_e7 as f64 / 10_000_000.0
+    }
+
+    pub fn timestamp(&self) -> DateTime<FixedOffset> {
+        DateTime::from_timestamp_millis(self.timestamp_ms).unwrap_or_default().fixed_offset()
+    }
+
+    /// same as `Location::haversine_distance`
+    pub fn haversine_distance(&self, other: &CompactLocation) -> f64 {
+        Point::from(self).haversine_distance(&Point::from(other))
+    }
+
+    /// same as `Location::speed_kmh`
+    pub fn speed_kmh(&self, other: &CompactLocation) -> Option<f64> {
+        let dist = self.haversine_distance(other);
+        let time = self.timestamp_ms / 1000 - other.timestamp_ms / 1000;
+
+        // 10 minute gap
+        if time > 0 && time < 600 {
+            let meter_second = dist / time as f64;
+            Some(meter_second * 3.6)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<&CompactLocation> for Point<f64> {
+    fn from(location: &CompactLocation) -> Point<f64> {
+        Point::new(location.latitude(), location.longitude())
+    }
+}
+
+impl CompactLocations {
+    pub fn new() -> Self {
+        CompactLocations::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// adds a location, converting it to the compact form
+    pub fn push(&mut self, location: &Location) {
+        let samples = location.activities.as_ref().map(|activities| {
+            let start = self.samples.len() as u32;
+            for sample in activities {
+                let first = self.activities.len() as u32;
+                for a in &sample.activities {
+                    let activity_type = self.type_id(&a.activity_type);
+                    self.activities.push(CompactActivity {
+                        activity_type,
+                        confidence: a.confidence.clamp(0, u8::MAX as i32) as u8,
+                    });
+                }
+                self.samples.push(CompactActivities {
+                    timestamp_ms: sample.timestamp.timestamp_millis(),
+                    activities: (first, sample.activities.len() as u16),
+                });
+            }
+            (start, activities.len() as u16)
+        });
+
+        self.locations.push(CompactLocation {
+            timestamp_ms: location.timestamp.timestamp_millis(),
+            latitude_e7: to_e7(location.latitude) as i32,
+            longitude_e7: to_e7(location.longitude) as i32,
+            accuracy: location.accuracy,
+            altitude: location.altitude,
+            samples,
+        });
+    }
+
+    /// the activity samples recorded at a location, if any
+    pub fn samples(&self, location: &CompactLocation) -> Option<&[CompactActivities]> {
+        location
+            .samples
+            .map(|(start, len)| &self.samples[start as usize..start as usize + len as usize])
+    }
+
+    /// the activities within a sample
+    pub fn activities(&self, sample: &CompactActivities) -> &[CompactActivity] {
+        let (start, len) = sample.activities;
+        &self.activities[start as usize..start as usize + len as usize]
+    }
+
+    /// the type of an activity
+    pub fn activity_type(&self, activity: &CompactActivity) -> &ActivityType {
+        &self.types[activity.activity_type as usize]
+    }
+
+    fn type_id(&mut self, activity_type: &ActivityType) -> u8 {
+        if let Some(id) = self.types.iter().position(|t| t == activity_type) {
+            return id as u8;
+        }
+        // the last id is kept free for UNKNOWN, which every type past it falls back to
+        if self.types.len() >= u8::MAX as usize && *activity_type != ActivityType::UNKNOWN {
+            return self.type_id(&ActivityType::UNKNOWN);
+        }
+        self.types.push(activity_type.clone());
+        (self.types.len() - 1) as u8
+    }
+
+    /// converts a location back into a `Location`
+    pub fn to_location(&self, location: &CompactLocation) -> Location {
+        Location {
+            timestamp: location.timestamp(),
+            latitude: location.latitude(),
+            longitude: location.longitude(),
+            accuracy: location.accuracy,
+            altitude: location.altitude,
+            activities: self.samples(location).map(|samples| {
+                samples
+                    .iter()
+                    .map(|sample| Activities {
+                        activities: self
+                            .activities(sample)
+                            .iter()
+                            .map(|a| Activity {
+                                activity_type: self.activity_type(a).clone(),
+                                confidence: a.confidence as i32,
+                            })
+                            .collect(),
+                        timestamp: DateTime::from_timestamp_millis(sample.timestamp_ms)
+                            .unwrap_or_default()
+                            .fixed_offset(),
+                    })
+                    .collect()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// converts every location back into a `Location`
+    pub fn iter(&self) -> impl Iterator<Item = Location> + '_ {
+        self.locations.iter().map(|l| self.to_location(l))
+    }
+
+    // true if the highest-confidence activity of any sample matches the glob, or is a kind of one that does
+    fn has_top_activity(&self, location: &CompactLocation, activity_type: &str) -> bool {
+        self.samples(location).unwrap_or_default().iter().any(|sample| {
+            self.activities(sample)
+                .iter()
+                .max_by_key(|a| a.confidence)
+                .is_some_and(|a| self.activity_type(a).matches(activity_type))
+        })
+    }
+}
+
+impl From<&[Location]> for CompactLocations {
+    fn from(locations: &[Location]) -> CompactLocations {
+        let mut compact = CompactLocations::new();
+        compact.locations.reserve(locations.len());
+        for location in locations {
+            compact.push(location);
+        }
+        compact
+    }
+}
+
+impl FromIterator<Location> for CompactLocations {
+    fn from_iter<I: IntoIterator<Item = Location>>(iter: I) -> CompactLocations {
+        let mut compact = CompactLocations::new();
+        for location in iter {
+            compact.push(&location);
+        }
+        compact
+    }
+}
+
+impl From<&CompactLocations> for Locations {
+    fn from(compact: &CompactLocations) -> Locations {
+        compact.iter().collect()
+    }
+}
+
+// filtering only drops entries from `locations`, the arenas are left as they are
+impl LocationsExt for CompactLocations {
+    fn average_time(&self) -> i64 {
+        let mut time = 0;
+        for pair in self.locations.windows(2) {
+            time += pair[1].timestamp_ms / 1000 - pair[0].timestamp_ms / 1000;
+        }
+        time / (self.len() as i64)
+    }
+
+    fn find_closest(&self, time: DateTime<FixedOffset>) -> Option<Location> {
+        let time = time.timestamp_millis();
+        let x = match self.locations.binary_search_by(|x| x.timestamp_ms.cmp(&time)) {
+            Ok(x) => x,
+            // if this is 0 or the len of locations return None
+            Err(x) if x > 0 && x < self.len() => x,
+            Err(_) => return None,
+        };
+        Some(self.to_location(&self.locations[x]))
+    }
+
+    fn sort_chronological(&mut self) {
+        self.locations.sort_by_key(|l| l.timestamp_ms);
+    }
+
+    fn filter_outliers(mut self) -> Self {
+        let Some(&first) = self.locations.first() else {
+            return self;
+        };
+
+        let mut last = first;
+        let mut kept = vec![first];
+        for &location in &self.locations[1..] {
+            if location.speed_kmh(&last).is_none_or(|speed| speed < 300.0) {
+                kept.push(location);
+                last = location;
+            }
+        }
+
+        self.locations = kept;
+        self.sort_chronological();
+        self
+    }
+
+    fn filter_by_activity(mut self, activity_type: String) -> Self {
+        let locations = std::mem::take(&mut self.locations);
+        self.locations = locations
+            .into_iter()
+            .filter(|l| self.has_top_activity(l, &activity_type))
+            .collect();
+        self.sort_chronological();
+        self
+    }
+
+    fn list_activities(&self) -> Vec<String> {
+        let mut activities_set: HashSet<String> = HashSet::new();
+
+        for location in &self.locations {
+            for sample in self.samples(location).unwrap_or_default() {
+                for activity in self.activities(sample) {
+                    activities_set.insert(self.activity_type(activity).as_str().to_string());
+                }
+            }
+        }
+
+        activities_set.into_iter().collect()
+    }
+
+    fn filter_by_distance(mut self, point: Point<f64>, distance: f64) -> Self {
+        self.locations
+            .retain(|l| Point::from(l).haversine_distance(&point) < distance);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ActivityType, CompactLocations, Locations, LocationsExt};
+
+    #[test]
+    fn compact_locations() {
+        let test_data = r#"{"locations": [
+            {"latitudeE7": -378224062, "longitudeE7": 1450703899, "accuracy": 12, "timestamp": "2024-01-01T09:12:00.123+10:00",
+             "activity": [{"activity": [{"type": "STILL", "confidence": 90}, {"type": "ON_FOOT", "confidence": 10}],
+                           "timestamp": "2024-01-01T09:11:58Z"}]},
+            {"latitudeE7": -378224100, "longitudeE7": 1450703899, "timestamp": "2024-01-01T09:13:00Z",
+             "activity": [{"activity": [{"type": "WALKING", "confidence": 80}], "timestamp": "2024-01-01T09:13:00Z"}]},
+            {"latitudeE7": 0, "longitudeE7": 0, "timestamp": "2024-01-01T09:14:00Z"}
+        ]}"#;
+        let locations = crate::deserialize(test_data).unwrap();
+        let compact = CompactLocations::from(&locations[..]);
+
+        let round_trip = Locations::from(&compact);
+        assert_eq!(round_trip[0].latitude, locations[0].latitude);
+        assert_eq!(round_trip[0].timestamp, locations[0].timestamp);
+        assert_eq!(round_trip[1].activities.as_ref().unwrap()[0].activities[0].activity_type, ActivityType::WALKING);
+        assert!(round_trip[2].activities.is_none());
+
+        // LocationsExt gives the same answers for both
+        assert_eq!(compact.average_time(), locations.average_time());
+        let mut activities = compact.list_activities();
+        activities.sort();
+        assert_eq!(activities, ["ON_FOOT", "STILL", "WALKING"]);
+        assert_eq!(compact.clone().filter_by_activity("{STILL,WALKING}".to_string()).len(), 2);
+        // the last location is thousands of kilometers away, a minute later
+        assert_eq!(compact.clone().filter_outliers().len(), 2);
+        let closest = compact.find_closest(locations[1].timestamp).unwrap();
+        assert_eq!(closest.latitude, locations[1].latitude);
+
+        // past 255 types, the rest are UNKNOWN
+        let many: Locations = (0..300)
+            .map(|i| {
+                let mut location = locations[1].clone();
+                location.activities.as_mut().unwrap()[0].activities[0].activity_type = ActivityType::Other(format!("TYPE_{}", i));
+                location
+            })
+            .collect();
+        let compact = CompactLocations::from(&many[..]);
+        let round_trip = Locations::from(&compact);
+        let type_of = |i: usize| round_trip[i].activities.as_ref().unwrap()[0].activities[0].activity_type.clone();
+        assert_eq!(type_of(254), ActivityType::Other("TYPE_254".to_string()));
+        assert_eq!(type_of(255), ActivityType::UNKNOWN);
+        assert_eq!(type_of(299), ActivityType::UNKNOWN);
+    }
+}
@@ -0,0 +1,305 @@
+//! Reading and writing locations as CSV, TSV, or with any other delimiter, for spreadsheets and pandas.
+//!
+//! Each row is one location. The top activity is the one with the highest confidence in any
+//! sample at the location, see `Location::top_activities`. With `activity_columns`, there is
+//! also a column for every activity type in the data, e.g. `activity:IN_CAR`, holding its
+//! highest confidence at that location, or nothing if it wasn't recorded there.
+//!
+//! When reading, columns are found by their header, so they may be in any order, and only
+//! `timestamp`, `latitude` and `longitude` are required. Any other column, such as the index
+//! pandas writes, is ignored.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use crate::xml::parse_time;
+use crate::{collect_sorted, format_timestamp, Activities, Activity, ActivityType, Error, Location, Locations, Result};
+
+// the start of the header of an activity type's column
+const ACTIVITY_PREFIX: &str = "activity:";
+
+/// a column of the CSV output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CsvColumn {
+    Timestamp,
+    Latitude,
+    Longitude,
+    Accuracy,
+    Altitude,
+    /// the top activity
+    Activity,
+    /// the confidence of the top activity
+    Confidence,
+}
+
+impl CsvColumn {
+    /// every column, in the default order
+    pub const ALL: [CsvColumn; 7] = [
+        CsvColumn::Timestamp,
+        CsvColumn::Latitude,
+        CsvColumn::Longitude,
+        CsvColumn::Accuracy,
+        CsvColumn::Altitude,
+        CsvColumn::Activity,
+        CsvColumn::Confidence,
+    ];
+
+    /// the column's header
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Timestamp => "timestamp",
+            CsvColumn::Latitude => "latitude",
+            CsvColumn::Longitude => "longitude",
+            CsvColumn::Accuracy => "accuracy",
+            CsvColumn::Altitude => "altitude",
+            CsvColumn::Activity => "activity",
+            CsvColumn::Confidence => "confidence",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<CsvColumn> {
+        CsvColumn::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+/// what `write_csv` writes
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// e.g. `b','` for CSV, or `b'\t'` for TSV
+    pub delimiter: u8,
+    /// the columns to write, in order
+    pub columns: Vec<CsvColumn>,
+    /// also write a column for every activity type, after the others
+    pub activity_columns: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            columns: CsvColumn::ALL.to_vec(),
+            activity_columns: false,
+        }
+    }
+}
+
+// the highest confidence of each activity type at a location
+fn confidences(location: &Location) -> HashMap<ActivityType, i32> {
+    let mut confidences: HashMap<ActivityType, i32> = HashMap::new();
+    for activity in location.top_activities() {
+        let confidence = confidences.entry(activity.activity_type).or_insert(activity.confidence);
+        *confidence = (*confidence).max(activity.confidence);
+    }
+    confidences
+}
+
+/// Writes locations with a header row, and one row per location.
+pub fn write_csv<W: Write>(writer: W, locations: &[Location], options: &CsvOptions) -> Result<()> {
+    let mut writer = ::csv::WriterBuilder::new().delimiter(options.delimiter).from_writer(writer);
+
+    // sorted, so the columns are the same from run to run
+    let activity_types: Vec<String> = if options.activity_columns {
+        locations
+            .iter()
+            .flat_map(|l| l.top_activities())
+            .map(|a| a.activity_type.as_str().to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let activity_headers = activity_types.iter().map(|t| format!("{}{}", ACTIVITY_PREFIX, t));
+    let header = options.columns.iter().map(|c| c.name().to_string()).chain(activity_headers);
+    writer.write_record(header).map_err(io::Error::from)?;
+
+    let mut row: Vec<String> = Vec::new();
+    for location in locations {
+        let top = location.top_activities().into_iter().next();
+        row.clear();
+        for column in &options.columns {
+            row.push(match column {
+                CsvColumn::Timestamp => format_timestamp(location.timestamp),
+                CsvColumn::Latitude => location.latitude.to_string(),
+                CsvColumn::Longitude => location.longitude.to_string(),
+                CsvColumn::Accuracy => location.accuracy.map(|a| a.to_string()).unwrap_or_default(),
+                CsvColumn::Altitude => location.altitude.map(|a| a.to_string()).unwrap_or_default(),
+                CsvColumn::Activity => top.as_ref().map(|a| a.activity_type.to_string()).unwrap_or_default(),
+                CsvColumn::Confidence => top.as_ref().map(|a| a.confidence.to_string()).unwrap_or_default(),
+            });
+        }
+
+        if !activity_types.is_empty() {
+            let confidences = confidences(location);
+            for activity_type in &activity_types {
+                let confidence = confidences.get(&activity_type.clone().into());
+                row.push(confidence.map(|c| c.to_string()).unwrap_or_default());
+            }
+        }
+
+        writer.write_record(&row).map_err(io::Error::from)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// deserialize locations written by `write_csv`, sorted by timestamp
+pub fn deserialize_csv(from: &str, delimiter: u8) -> Result<Locations> {
+    collect_sorted(|tx| read_csv(from.as_bytes(), delimiter, tx))
+}
+
+/// Reads a CSV file, such as one written by `write_csv`, and decodes the rows on-the-fly,
+/// in the same manner as `deserialize_streaming`. Locations are sent in file order.
+///
+/// # Arguments
+///
+/// * `from` - The path to the file.
+/// * `tx` - The `Sender` channel to send the decoded `Location` objects.
+/// * `delimiter` - e.g. `b','` for CSV, or `b'\t'` for TSV.
+pub fn deserialize_csv_streaming(from: PathBuf, tx: Sender<Location>, delimiter: u8) -> Result<()> {
+    let file = File::open::<PathBuf>(from)?;
+    read_csv(BufReader::new(file), delimiter, tx)
+}
+
+fn record_error(index: usize, offset: Option<u64>, e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::Record {
+        index,
+        offset,
+        source: e.into(),
+    }
+}
+
+fn read_csv<R: Read>(reader: R, delimiter: u8, tx: Sender<Location>) -> Result<()> {
+    let mut reader = ::csv::ReaderBuilder::new().delimiter(delimiter).from_reader(reader);
+
+    // where each column is, and the activity type columns
+    let header = reader.headers().map_err(io::Error::from)?.clone();
+    let mut columns: HashMap<CsvColumn, usize> = HashMap::new();
+    let mut activity_columns: Vec<(ActivityType, usize)> = Vec::new();
+    for (i, name) in header.iter().enumerate() {
+        if let Some(activity_type) = name.strip_prefix(ACTIVITY_PREFIX) {
+            activity_columns.push((activity_type.to_string().into(), i));
+        } else if let Some(column) = CsvColumn::from_name(name) {
+            columns.insert(column, i);
+        }
+    }
+    for required in [CsvColumn::Timestamp, CsvColumn::Latitude, CsvColumn::Longitude] {
+        if !columns.contains_key(&required) {
+            let message = format!("no {} column", required.name());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+    }
+
+    for (index, row) in reader.records().enumerate() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) if e.is_io_error() => return Err(io::Error::from(e).into()),
+            Err(e) => return Err(record_error(index, e.position().map(|p| p.byte()), e)),
+        };
+        let offset = row.position().map(|p| p.byte());
+
+        // a missing or empty cell is None
+        let cell = |column: CsvColumn| columns.get(&column).and_then(|&i| row.get(i)).filter(|s| !s.is_empty());
+        let number = |column: CsvColumn| -> Result<Option<f64>> {
+            cell(column)
+                .map(|s| s.parse::<f64>().map_err(|e| record_error(index, offset, format!("invalid {} {:?}: {}", column.name(), s, e))))
+                .transpose()
+        };
+
+        let timestamp = cell(CsvColumn::Timestamp).unwrap_or_default();
+        let timestamp =
+            parse_time(timestamp).ok_or_else(|| record_error(index, offset, format!("invalid timestamp {:?}", timestamp)))?;
+        let latitude = number(CsvColumn::Latitude)?.ok_or_else(|| record_error(index, offset, "no latitude"))?;
+        let longitude = number(CsvColumn::Longitude)?.ok_or_else(|| record_error(index, offset, "no longitude"))?;
+
+        // the activity columns have everything the top activity does, so take those over it
+        let mut activities: Vec<Activity> = Vec::new();
+        for (activity_type, i) in &activity_columns {
+            if let Some(confidence) = row.get(*i).filter(|s| !s.is_empty()) {
+                let confidence = confidence
+                    .parse::<f64>()
+                    .map_err(|e| record_error(index, offset, format!("invalid {} {:?}: {}", activity_type, confidence, e)))?;
+                activities.push(Activity {
+                    activity_type: activity_type.clone(),
+                    confidence: confidence.round() as i32,
+                });
+            }
+        }
+        if activities.is_empty() {
+            if let Some(activity_type) = cell(CsvColumn::Activity) {
+                activities.push(Activity {
+                    activity_type: activity_type.to_string().into(),
+                    confidence: number(CsvColumn::Confidence)?.map_or(100, |c| c.round() as i32),
+                });
+            }
+        }
+
+        let location = Location {
+            timestamp,
+            latitude,
+            longitude,
+            accuracy: number(CsvColumn::Accuracy)?.map(|a| a.round() as i32),
+            altitude: number(CsvColumn::Altitude)?.map(|a| a.round() as i32),
+            activities: (!activities.is_empty()).then(|| vec![Activities { activities, timestamp }]),
+            ..Default::default()
+        };
+        if tx.send(location).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, ActivityType, CsvColumn, CsvOptions};
+
+    #[test]
+    fn csv_round_trip() {
+        let locations = fixtures::locations();
+
+        let mut out = Vec::new();
+        crate::write_csv(&mut out, &locations, &CsvOptions::default()).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(
+            csv,
+            "timestamp,latitude,longitude,accuracy,altitude,activity,confidence\n\
+             2024-01-01T09:12:00.123+10:00,-37.8224062,145.0703899,12,52,WALKING,90\n\
+             2024-01-01T09:13:00+10:00,-37.82241,145.0703899,,,,\n"
+        );
+        let read = crate::deserialize_csv(&csv, b',').unwrap();
+        assert_eq!(read[0].timestamp, locations[0].timestamp);
+        assert_eq!((read[0].latitude, read[0].accuracy), (-37.8224062, Some(12)));
+        assert_eq!(read[0].top_activity_type(), Some(ActivityType::WALKING));
+        assert!(read[1].activities.is_none());
+
+        // tab separated, with a column for every activity, and the top activity left out
+        let options = CsvOptions {
+            delimiter: b'\t',
+            columns: vec![CsvColumn::Timestamp, CsvColumn::Latitude, CsvColumn::Longitude],
+            activity_columns: true,
+        };
+        let mut out = Vec::new();
+        crate::write_csv(&mut out, &locations, &options).unwrap();
+        let tsv = String::from_utf8(out).unwrap();
+        assert!(tsv.starts_with("timestamp\tlatitude\tlongitude\tactivity:STILL\tactivity:WALKING\n"));
+        let read = crate::deserialize_csv(&tsv, b'\t').unwrap();
+        let activities = &read[0].activities.as_ref().unwrap()[0].activities;
+        assert_eq!(activities.len(), 2);
+        assert_eq!(read[0].top_activity_type(), Some(ActivityType::WALKING));
+
+        // other columns, such as the index pandas writes, are ignored
+        let pandas = ",timestamp,latitude,longitude,source\n0,2024-01-01T09:12:00Z,-37.8,145.0,WIFI\n";
+        let read = crate::deserialize_csv(pandas, b',').unwrap();
+        assert!(read[0].activities.is_none());
+
+        let error = crate::deserialize_csv("timestamp,latitude,longitude\nyesterday,1,2\n", b',').unwrap_err();
+        assert!(matches!(error, crate::Error::Record { index: 0, .. }));
+    }
+}
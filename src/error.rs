@@ -0,0 +1,179 @@
+//! Errors produced while reading location history.
+
+use std::fmt;
+use std::io;
+use struson::reader::ReaderError;
+
+/// Errors produced while reading location history
+#[derive(Debug)]
+pub enum Error {
+    /// the file couldn't be opened or read
+    Io(io::Error),
+    /// the JSON couldn't be read, e.g. it is malformed, or the expected array is missing
+    Json(ReaderError),
+    /// the XML of a GPX or KML file couldn't be read
+    Xml(quick_xml::Error),
+    /// a single record couldn't be decoded
+    Record {
+        /// position of the record within its array, starting at 0
+        index: usize,
+        /// byte offset of the record within the (decompressed) file, if known
+        offset: Option<u64>,
+        /// why the record couldn't be decoded
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// result type used throughout this crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "failed to read location history: {}", e),
+            Error::Json(e) => write!(f, "invalid location history JSON: {}", e),
+            Error::Xml(e) => write!(f, "invalid XML: {}", e),
+            Error::Record {
+                index,
+                offset: Some(offset),
+                source,
+            } => write!(f, "invalid record {} at byte {}: {}", index, offset, source),
+            Error::Record {
+                index,
+                offset: None,
+                source,
+            } => write!(f, "invalid record {}: {}", index, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Xml(e) => Some(e),
+            Error::Record { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ReaderError> for Error {
+    fn from(e: ReaderError) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Error {
+        Error::Xml(e)
+    }
+}
+
+/// a record which was skipped when reading leniently
+#[derive(Debug, Clone)]
+pub struct SkippedRecord {
+    /// position of the record within its array, starting at 0
+    pub index: usize,
+    /// byte offset of the record within the (decompressed) file, if known
+    pub offset: Option<u64>,
+    /// why the record couldn't be decoded
+    pub reason: String,
+}
+
+/// the records which were skipped when reading leniently, in file order
+#[derive(Debug, Clone, Default)]
+pub struct SkipReport {
+    pub skipped: Vec<SkippedRecord>,
+}
+
+impl SkipReport {
+    /// number of records skipped
+    pub fn count(&self) -> usize {
+        self.skipped.len()
+    }
+
+    /// true if every record was read
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    pub(crate) fn skip(&mut self, index: usize, offset: Option<u64>, reason: impl fmt::Display) {
+        self.skipped.push(SkippedRecord {
+            index,
+            offset,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+impl fmt::Display for SkippedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "record {} at byte {}: {}", self.index, offset, self.reason),
+            None => write!(f, "record {}: {}", self.index, self.reason),
+        }
+    }
+}
+
+impl fmt::Display for SkipReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} records skipped", self.count())?;
+        for record in &self.skipped {
+            write!(f, "\n  {}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn bad_record_position() {
+        let test_data = r#"{"locations": [
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:00:00Z"},
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "yesterday"}
+        ]}"#;
+
+        match crate::deserialize(test_data) {
+            Err(Error::Record { index, offset, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(offset, test_data.rfind(r#"{"latitudeE7""#).map(|o| o as u64));
+            }
+            other => panic!("expected a record error, got {:?}", other),
+        }
+
+        assert!(matches!(crate::deserialize(r#"{"locations": [}"#), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn lenient_skip_report() {
+        let test_data = r#"{"locations": [
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:00:00Z"},
+            {"longitudeE7": 2, "timestamp": "2024-01-01T00:01:00Z"},
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:02:00Z", "activity": "odd"},
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:03:00Z"},
+            {"latitudeE7": 1, "longitudeE7": 2, "timest"#;
+
+        let path = std::env::temp_dir().join(format!("location_history_lenient_{}.json", std::process::id()));
+        std::fs::write(&path, test_data).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let report = crate::deserialize_streaming_lenient(path.clone(), tx).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rx.iter().count(), 2);
+        let indices: Vec<usize> = report.skipped.iter().map(|r| r.index).collect();
+        assert_eq!(indices, [1, 2, 4]);
+        assert!(report.skipped[0].reason.contains("latitudeE7"));
+        assert_eq!(report.skipped[1].offset, test_data.find(r#"{"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:02"#).map(|o| o as u64));
+    }
+}
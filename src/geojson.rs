@@ -0,0 +1,164 @@
+//! Writing locations as GeoJSON, for GIS tools such as QGIS.
+//!
+//! The output is a single `FeatureCollection`, with one feature per line so that large
+//! histories can be streamed out, and read with line-oriented tools. Coordinates are
+//! `[longitude, latitude]`, as GeoJSON requires. Timestamps keep the timezone they were
+//! recorded in.
+
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use std::io::{self, Write};
+
+use crate::segments::most_common_activity;
+use crate::{format_timestamp, local_date, split_tracks, ActivityType, Location, Result, TrackSplit};
+
+/// what each feature of the GeoJSON output is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeoJsonMode {
+    /// a `Point` for each location
+    Points,
+    /// a `LineString` for each calendar day
+    Days,
+    /// a `LineString` for each run of locations with the same top activity, see `TrackSplit::Activity`
+    Segments,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+enum Geometry {
+    Point([f64; 2]),
+    LineString(Vec<[f64; 2]>),
+}
+
+#[derive(Serialize)]
+struct Feature<P> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    geometry: Geometry,
+    properties: P,
+}
+
+#[derive(Serialize)]
+struct PointProperties {
+    timestamp: String,
+    accuracy: Option<i32>,
+    altitude: Option<i32>,
+    activity: Option<ActivityType>,
+}
+
+#[derive(Serialize)]
+struct TrackProperties {
+    /// the day the track starts on, in the timezone it was recorded in
+    date: NaiveDate,
+    /// the top activity of the most locations in the track
+    activity: Option<ActivityType>,
+    start: String,
+    end: String,
+    points: usize,
+}
+
+fn position(location: &Location) -> [f64; 2] {
+    [location.longitude, location.latitude]
+}
+
+/// Writes locations as a GeoJSON `FeatureCollection`, of points or of tracks depending on `mode`.
+/// The locations are expected to be in chronological order.
+pub fn write_geojson<W: Write>(mut writer: W, locations: &[Location], mode: GeoJsonMode) -> Result<()> {
+    writeln!(writer, r#"{{"type": "FeatureCollection", "features": ["#)?;
+
+    match mode {
+        GeoJsonMode::Points => {
+            for (i, location) in locations.iter().enumerate() {
+                let feature = Feature {
+                    kind: "Feature",
+                    geometry: Geometry::Point(position(location)),
+                    properties: PointProperties {
+                        timestamp: format_timestamp(location.timestamp),
+                        accuracy: location.accuracy,
+                        altitude: location.altitude,
+                        activity: location.top_activity_type(),
+                    },
+                };
+                write_feature(&mut writer, &feature, i == 0)?;
+            }
+        }
+        GeoJsonMode::Days | GeoJsonMode::Segments => {
+            let split = if mode == GeoJsonMode::Days { TrackSplit::Day } else { TrackSplit::Activity };
+            for (i, track) in split_tracks(locations, split).into_iter().enumerate() {
+                let mut line: Vec<[f64; 2]> = track.iter().map(position).collect();
+                // a LineString needs two positions, so a lone location is repeated, rather than
+                // mixing in a Point, as GIS layers only hold a single type of geometry
+                if line.len() == 1 {
+                    line.push(line[0]);
+                }
+
+                let feature = Feature {
+                    kind: "Feature",
+                    geometry: Geometry::LineString(line),
+                    properties: TrackProperties {
+                        date: local_date(&track[0]),
+                        activity: most_common_activity(track),
+                        start: format_timestamp(track[0].timestamp),
+                        end: format_timestamp(track[track.len() - 1].timestamp),
+                        points: track.len(),
+                    },
+                };
+                write_feature(&mut writer, &feature, i == 0)?;
+            }
+        }
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "]}}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_feature<W: Write, P: serde::Serialize>(writer: &mut W, feature: &Feature<P>, first: bool) -> Result<()> {
+    if !first {
+        writeln!(writer, ",")?;
+    }
+    serde_json::to_writer(&mut *writer, feature).map_err(io::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, ActivityType, GeoJsonMode};
+
+    #[test]
+    fn geojson_modes() {
+        let mut locations = fixtures::locations();
+        locations.push(fixtures::at(&locations[1], "2024-01-01T09:14:00+10:00"));
+        locations[1] = fixtures::with_activity(locations[1].clone(), ActivityType::IN_BUS);
+
+        let features = |mode| -> Vec<serde_json::Value> {
+            let mut out = Vec::new();
+            crate::write_geojson(&mut out, &locations, mode).unwrap();
+            let collection: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            assert_eq!(collection["type"], "FeatureCollection");
+            collection["features"].as_array().unwrap().clone()
+        };
+
+        let points = features(GeoJsonMode::Points);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0]["geometry"]["type"], "Point");
+        assert_eq!(points[0]["geometry"]["coordinates"], serde_json::json!([145.0703899, -37.8224062]));
+        assert_eq!(points[0]["properties"]["timestamp"], "2024-01-01T09:12:00.123+10:00");
+        assert_eq!(points[0]["properties"]["accuracy"], 12);
+        assert_eq!(points[0]["properties"]["activity"], "WALKING");
+        assert!(points[2]["properties"]["activity"].is_null());
+
+        let days = features(GeoJsonMode::Days);
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0]["geometry"]["coordinates"].as_array().unwrap().len(), 3);
+        assert_eq!(days[0]["properties"]["date"], "2024-01-01");
+        assert_eq!(days[0]["properties"]["end"], "2024-01-01T09:14:00+10:00");
+
+        let segments = features(GeoJsonMode::Segments);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0]["properties"]["points"], 1);
+        assert_eq!(segments[0]["geometry"]["coordinates"].as_array().unwrap().len(), 2);
+        assert_eq!(segments[1]["properties"]["activity"], "IN_BUS");
+    }
+}
@@ -0,0 +1,275 @@
+//! Reading and writing locations as GPX 1.1, for use with GPS loggers and other GPS tools.
+//!
+//! Each track (see `split_tracks`) becomes a `<trk>` with a single `<trkseg>`. A point's top
+//! activity is written as its `<type>`, and the track's most common one as the track's
+//! `<type>`. GPX has no field for a horizontal accuracy in meters, only the unitless `<hdop>`.
+//! As a number of GPS loggers do, the accuracy is written as the `<hdop>` anyway, so that other
+//! tools can show or filter on it, but it is only read back from an `<lh:accuracy>` extension,
+//! as an `<hdop>` from elsewhere isn't in meters.
+//!
+//! When reading, every `<trkpt>` becomes a `Location`, whichever track it is in. A `<type>`
+//! becomes a single activity with 100% confidence, so a file written here reads back the same.
+//! A `<trkpt>` without a `<time>` can't be placed in the history, and is skipped over.
+
+use log::warn;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use crate::xml::{escape_xml, format_utc, parse_time, start_offset};
+use crate::segments::most_common_activity;
+use crate::{collect_sorted, local_date, split_tracks, Activities, Activity, Error, Location, Locations, Result, TrackSplit};
+
+const EXTENSIONS_NAMESPACE: &str = "https://github.com/leighleighleigh/location_history";
+
+/// Writes locations as GPX 1.1, with one `<trk>` per day or per trip.
+/// The locations are expected to be in chronological order.
+pub fn write_gpx<W: Write>(mut writer: W, locations: &[Location], split: TrackSplit) -> Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<gpx version="1.1" creator="location_history" xmlns="http://www.topografix.com/GPX/1/1" xmlns:lh="{}">"#,
+        EXTENSIONS_NAMESPACE
+    )?;
+
+    for track in split_tracks(locations, split) {
+        writeln!(writer, "  <trk>")?;
+        writeln!(writer, "    <name>{}</name>", track_name(track, split))?;
+        if let Some(activity_type) = most_common_activity(track) {
+            writeln!(writer, "    <type>{}</type>", escape_xml(activity_type.as_str()))?;
+        }
+        writeln!(writer, "    <trkseg>")?;
+        for location in track {
+            write_point(&mut writer, location)?;
+        }
+        writeln!(writer, "    </trkseg>")?;
+        writeln!(writer, "  </trk>")?;
+    }
+
+    writeln!(writer, "</gpx>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_point<W: Write>(writer: &mut W, location: &Location) -> Result<()> {
+    writeln!(
+        writer,
+        r#"      <trkpt lat="{:.7}" lon="{:.7}">"#,
+        location.latitude, location.longitude
+    )?;
+    // the order of the elements is fixed by the schema
+    if let Some(altitude) = location.altitude {
+        writeln!(writer, "        <ele>{}</ele>", altitude)?;
+    }
+    writeln!(writer, "        <time>{}</time>", format_utc(location.timestamp))?;
+    if let Some(activity_type) = location.top_activity_type() {
+        writeln!(writer, "        <type>{}</type>", escape_xml(activity_type.as_str()))?;
+    }
+    if let Some(accuracy) = location.accuracy {
+        writeln!(writer, "        <hdop>{}</hdop>", accuracy)?;
+        writeln!(writer, "        <extensions>")?;
+        writeln!(writer, "          <lh:accuracy>{}</lh:accuracy>", accuracy)?;
+        writeln!(writer, "        </extensions>")?;
+    }
+    writeln!(writer, "      </trkpt>")?;
+    Ok(())
+}
+
+// e.g. "2024-01-01" for a day, or "2024-01-01 09:12" for a trip, in the track's own timezone
+fn track_name(track: &[Location], split: TrackSplit) -> String {
+    match split {
+        TrackSplit::Day => local_date(&track[0]).to_string(),
+        TrackSplit::Trip | TrackSplit::Activity => track[0].timestamp.format("%Y-%m-%d %H:%M").to_string(),
+    }
+}
+
+/// deserialize the track points of a GPX file into locations, sorted by timestamp
+pub fn deserialize_gpx(from: &str) -> Result<Locations> {
+    collect_sorted(|tx| read_gpx(from.as_bytes(), tx))
+}
+
+/// Reads a GPX file and decodes the track points on-the-fly, in the same manner as
+/// `deserialize_streaming`. Locations are sent in file order.
+///
+/// # Arguments
+///
+/// * `from` - The path to the `.gpx` file.
+/// * `tx` - The `Sender` channel to send the decoded `Location` objects.
+pub fn deserialize_gpx_streaming(from: PathBuf, tx: Sender<Location>) -> Result<()> {
+    let file = File::open::<PathBuf>(from)?;
+    read_gpx(BufReader::new(file), tx)
+}
+
+// the parts of a <trkpt> which are kept, as written in the file
+#[derive(Default)]
+struct TrackPoint {
+    offset: u64,
+    lat: Option<String>,
+    lon: Option<String>,
+    ele: Option<String>,
+    time: Option<String>,
+    activity_type: Option<String>,
+    accuracy: Option<String>,
+}
+
+impl TrackPoint {
+    fn into_location(self) -> std::result::Result<Location, String> {
+        fn number(value: Option<String>, name: &str) -> std::result::Result<Option<f64>, String> {
+            value
+                .map(|v| v.trim().parse::<f64>().map_err(|e| format!("invalid {} {:?}: {}", name, v, e)))
+                .transpose()
+        }
+
+        let latitude = number(self.lat, "lat")?.ok_or("trkpt has no lat")?;
+        let longitude = number(self.lon, "lon")?.ok_or("trkpt has no lon")?;
+        let altitude = number(self.ele, "ele")?;
+        let accuracy = number(self.accuracy, "accuracy")?;
+        let time = self.time.ok_or("trkpt has no time")?;
+        let timestamp = parse_time(&time).ok_or_else(|| format!("invalid time {:?}", time))?;
+
+        Ok(Location {
+            timestamp,
+            latitude,
+            longitude,
+            altitude: altitude.map(|a| a.round() as i32),
+            accuracy: accuracy.map(|a| a.round() as i32),
+            activities: self.activity_type.map(|activity_type| {
+                vec![Activities {
+                    activities: vec![Activity {
+                        activity_type: activity_type.into(),
+                        confidence: 100,
+                    }],
+                    timestamp,
+                }]
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+// a track point with the position in the attributes of its <trkpt>
+fn start_point<R>(reader: &Reader<R>, e: &BytesStart) -> Result<TrackPoint> {
+    let attribute = |name: &str| -> Result<Option<String>> {
+        Ok(match e.try_get_attribute(name).map_err(quick_xml::Error::from)? {
+            Some(value) => Some(value.unescape_value()?.into_owned()),
+            None => None,
+        })
+    };
+    Ok(TrackPoint {
+        offset: start_offset(reader, e),
+        lat: attribute("lat")?,
+        lon: attribute("lon")?,
+        ..Default::default()
+    })
+}
+
+// sends a track point once it has all been read, counting it in `index`, or skips it if it has
+// no time. false once the receiver has hung up
+fn send_point(tx: &Sender<Location>, index: &mut usize, point: TrackPoint) -> Result<bool> {
+    if point.time.is_none() {
+        warn!("skipping trkpt at byte {}, which has no time", point.offset);
+        return Ok(true);
+    }
+
+    let offset = point.offset;
+    let location = point.into_location().map_err(|e| Error::Record {
+        index: *index,
+        offset: Some(offset),
+        source: e.into(),
+    })?;
+    *index += 1;
+    Ok(tx.send(location).is_ok())
+}
+
+fn read_gpx<R: BufRead>(reader: R, tx: Sender<Location>) -> Result<()> {
+    let mut reader = Reader::from_reader(reader);
+    reader.config_mut().trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut index = 0;
+    // the track point being read, and the element within it
+    let mut point: Option<TrackPoint> = None;
+    let mut element: Vec<u8> = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if e.local_name().as_ref() == b"trkpt" => point = Some(start_point(&reader, &e)?),
+            // a self-closing <trkpt/>, which has nothing but its position
+            Event::Empty(e) if e.local_name().as_ref() == b"trkpt" => {
+                if !send_point(&tx, &mut index, start_point(&reader, &e)?)? {
+                    return Ok(());
+                }
+            }
+            Event::Start(e) if point.is_some() => element = e.local_name().as_ref().to_vec(),
+            Event::Text(text) => {
+                if let Some(point) = point.as_mut() {
+                    let value = Some(text.unescape()?.into_owned());
+                    match element.as_slice() {
+                        b"ele" => point.ele = value,
+                        b"time" => point.time = value,
+                        b"type" => point.activity_type = value,
+                        b"accuracy" => point.accuracy = value,
+                        _ => (),
+                    }
+                }
+            }
+            Event::End(e) if e.local_name().as_ref() == b"trkpt" => {
+                if let Some(point) = point.take() {
+                    if !send_point(&tx, &mut index, point)? {
+                        return Ok(());
+                    }
+                }
+            }
+            Event::End(_) => element.clear(),
+            Event::Eof => return Ok(()),
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, TrackSplit};
+
+    #[test]
+    fn gpx_tracks() {
+        let mut locations = fixtures::locations();
+        locations.push(fixtures::at(&locations[1], "2024-01-01T18:00:00+10:00"));
+
+        let mut out = Vec::new();
+        crate::write_gpx(&mut out, &locations, TrackSplit::Day).unwrap();
+        let gpx = String::from_utf8(out).unwrap();
+        assert_eq!(gpx.matches("<trk>").count(), 1);
+        assert!(gpx.contains("<name>2024-01-01</name>\n    <type>WALKING</type>"));
+        assert!(gpx.contains(r#"<trkpt lat="-37.8224062" lon="145.0703899">
+        <ele>52</ele>
+        <time>2023-12-31T23:12:00.123Z</time>
+        <type>WALKING</type>
+        <hdop>12</hdop>
+        <extensions>
+          <lh:accuracy>12</lh:accuracy>"#));
+
+        // reading it back gives the same locations, in UTC
+        let read = crate::deserialize_gpx(&gpx).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[0].timestamp, locations[0].timestamp);
+        assert_eq!((read[0].latitude, read[0].altitude, read[0].accuracy), (-37.8224062, Some(52), Some(12)));
+        assert_eq!(read[0].top_activity_type(), Some(crate::ActivityType::WALKING));
+        // a point without a time is skipped, as is a self-closing one, but a bad time is an error
+        let untimed = gpx.replace("<time>2023-12-31T23:12:00.123Z</time>", "");
+        assert_eq!(crate::deserialize_gpx(&untimed).unwrap().len(), 2);
+        let empty = gpx.replace("<trkseg>", r#"<trkseg><trkpt lat="-37.8" lon="145.0"/>"#);
+        assert_eq!(crate::deserialize_gpx(&empty).unwrap().len(), 3);
+        assert!(crate::deserialize_gpx(&gpx.replace("2023-12-31T23:12:00.123Z", "later")).is_err());
+
+        let mut out = Vec::new();
+        crate::write_gpx(&mut out, &locations, TrackSplit::Trip).unwrap();
+        let gpx = String::from_utf8(out).unwrap();
+        assert_eq!(gpx.matches("<trk>").count(), 2);
+        assert!(gpx.contains("<name>2024-01-01 18:00</name>"));
+    }
+}
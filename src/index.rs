@@ -0,0 +1,197 @@
+//! Sidecar index of where each month starts within a `Records.json`.
+//!
+//! Records.json is written in chronological order, so reading a date range only needs the
+//! records from the first one of the starting month onwards. The index is written next to
+//! the source as `<source>.index`, and is only used while the source has the same size and
+//! modification time as when the index was built.
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use struson::json_path;
+use struson::reader::{JsonReader, JsonStreamReader};
+
+use crate::cache::source_stamp;
+use crate::{next_record, open_records, parse_record_timestamp, Location, LocationReader, Result};
+
+/// where the first record of a month is, within a `Records.json`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MonthOffset {
+    pub year: i32,
+    /// 1 to 12, in UTC
+    pub month: u32,
+    /// position of the record within the locations array
+    pub index: usize,
+    /// byte offset of the record within the (decompressed) file
+    pub offset: u64,
+}
+
+/// the start of each month within a `Records.json`, in file order
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MonthIndex {
+    #[serde(rename = "sourceSize")]
+    source_size: u64,
+    #[serde(rename = "sourceModified")]
+    source_modified: (u64, u32),
+    pub months: Vec<MonthOffset>,
+}
+
+// only the timestamp is decoded while indexing, the other fields are skipped over
+#[derive(Deserialize)]
+pub(crate) struct RecordTimestamp {
+    #[serde(flatten, deserialize_with = "parse_record_timestamp")]
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+/// where the index for `source` is written, e.g. `Records.json.index`
+pub fn index_path(source: &Path) -> PathBuf {
+    let mut name = source.file_name().unwrap_or_default().to_os_string();
+    name.push(".index");
+    source.with_file_name(name)
+}
+
+impl MonthIndex {
+    /// Reads through `source`, noting down where each month starts.
+    /// A record which is older than the month before it doesn't start a new month.
+    pub fn build(source: &Path) -> Result<MonthIndex> {
+        let (source_size, secs, nanos) = source_stamp(source)?;
+        let mut index = MonthIndex {
+            source_size,
+            source_modified: (secs, nanos),
+            months: Vec::new(),
+        };
+
+        let mut json_reader = JsonStreamReader::new(open_records(source)?);
+        json_reader.seek_to(&json_path!["locations"])?;
+        json_reader.begin_array()?;
+
+        let mut i = 0;
+        while json_reader.has_next()? {
+            let offset = json_reader.current_position(false).data_pos;
+            let record: RecordTimestamp = next_record(&mut json_reader, i)?;
+
+            let utc = record.timestamp.with_timezone(&Utc);
+            let month = (utc.year(), utc.month());
+            if index.months.last().is_none_or(|last| (last.year, last.month) < month) {
+                index.months.push(MonthOffset {
+                    year: month.0,
+                    month: month.1,
+                    index: i,
+                    offset: offset.ok_or_else(|| io::Error::other("record offset unknown"))?,
+                });
+            }
+            i += 1;
+        }
+
+        Ok(index)
+    }
+
+    /// Reads the index for `source`, if there is one, and it is still up to date.
+    pub fn read(source: &Path) -> Result<Option<MonthIndex>> {
+        let data = match fs::read(index_path(source)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let index: MonthIndex = serde_json::from_slice(&data).map_err(io::Error::from)?;
+
+        let (size, secs, nanos) = source_stamp(source)?;
+        if (index.source_size, index.source_modified) != (size, (secs, nanos)) {
+            return Ok(None);
+        }
+        Ok(Some(index))
+    }
+
+    /// Writes the index next to `source`.
+    pub fn write(&self, source: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+        fs::write(index_path(source), data)?;
+        Ok(())
+    }
+
+    /// The month to start reading from to find every record at or after `start`, or `None`
+    /// if that is the start of the file.
+    pub fn locate(&self, start: DateTime<FixedOffset>) -> Option<&MonthOffset> {
+        let utc = start.with_timezone(&Utc);
+        let month = (utc.year(), utc.month());
+
+        self.months
+            .iter()
+            .rev()
+            .find(|m| (m.year, m.month) <= month)
+            .filter(|m| m.index > 0)
+    }
+}
+
+/// Same as `deserialize_streaming`, but only sends the locations from `start` (inclusive)
+/// until `end` (exclusive). If an up-to-date `MonthIndex` is next to the file, reading
+/// starts at the month `start` is in, rather than at the beginning of the file.
+/// Reading stops at the first record after `end`, as Records.json is in chronological order.
+pub fn deserialize_streaming_range(
+    from: PathBuf,
+    tx: Sender<Location>,
+    start: Option<DateTime<FixedOffset>>,
+    end: Option<DateTime<FixedOffset>>,
+) -> Result<()> {
+    let month = match start {
+        Some(start) => MonthIndex::read(&from)?.and_then(|index| index.locate(start).cloned()),
+        None => None,
+    };
+    let reader = match month {
+        Some(month) => LocationReader::open_at(&from, &month)?,
+        None => LocationReader::open(&from)?,
+    };
+
+    for location in reader.time_window(start, end, Duration::zero()) {
+        if tx.send(location?).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{LocationReader, MonthIndex};
+    use chrono::DateTime;
+
+    #[test]
+    fn month_index() {
+        let months = ["2023-11-30T23:00:00Z", "2023-12-01T00:00:00Z", "2023-12-24T00:00:00+10:00", "2024-02-01T00:00:00Z"];
+        let records: Vec<String> = months
+            .iter()
+            .map(|t| format!(r#"{{"latitudeE7": 1, "longitudeE7": 2, "timestamp": "{}"}}"#, t))
+            .collect();
+        let test_data = format!("{{\n  \"locations\": [{}]\n}}", records.join(", "));
+
+        let dir = std::env::temp_dir().join(format!("location_history_index_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Records.json");
+        std::fs::write(&source, &test_data).unwrap();
+
+        let index = MonthIndex::build(&source).unwrap();
+        let starts: Vec<(i32, u32, usize)> = index.months.iter().map(|m| (m.year, m.month, m.index)).collect();
+        assert_eq!(starts, [(2023, 11, 0), (2023, 12, 1), (2024, 2, 3)]);
+        assert_eq!(index.months[1].offset, test_data.find(&records[1]).unwrap() as u64);
+
+        assert!(MonthIndex::read(&source).unwrap().is_none());
+        index.write(&source).unwrap();
+        let index = MonthIndex::read(&source).unwrap().unwrap();
+
+        // January has no records, so reading starts at December
+        let month = index.locate(DateTime::parse_from_rfc3339("2024-01-15T00:00:00Z").unwrap()).unwrap();
+        let locations: Vec<_> = LocationReader::open_at(&source, month).unwrap().map(Result::unwrap).collect();
+        assert_eq!(locations.len(), 3);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let start = DateTime::parse_from_rfc3339("2023-12-20T00:00:00Z").ok();
+        let end = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").ok();
+        crate::deserialize_streaming_range(source.clone(), tx, start, end).unwrap();
+        assert_eq!(rx.iter().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//! Opening of location history input files.
+//!
+//! Besides a plain `Records.json`, the Takeout archive itself (`.zip` or `.tgz`) can be
+//! passed in. The `Records.json` inside is located and decompressed on-the-fly, so a
+//! multi-gigabyte export never has to be extracted to disk.
+//!
+//! Compressed files (`Records.json.gz`, `.zst`, `.xz`) are decoded on-the-fly in the same way.
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use xz2::read::XzDecoder;
+use zip::{CompressionMethod, ZipArchive};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+
+// size of a tar header block, entry data is padded to a multiple of this
+const TAR_BLOCK: u64 = 512;
+
+/// Opens `Records.json` for reading, from either the file itself, or from inside a
+/// Takeout `.zip` / `.tgz` archive. gzip, zstd and xz compressed files are decoded as
+/// they are read. Archives and compression are detected by their magic bytes,
+/// rather than the file extension.
+pub fn open_records(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let magic = reader.fill_buf()?;
+
+    let mut decoded: Box<dyn Read + Send> = if magic.starts_with(ZIP_MAGIC) {
+        return open_zip(path);
+    } else if magic.starts_with(GZIP_MAGIC) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(XzDecoder::new_multi_decoder(reader))
+    } else {
+        return Ok(Box::new(reader));
+    };
+
+    // a compressed file may be a tarball, like the .tgz Takeout archives.
+    // peek at the first header block, then put it back in front of the stream.
+    let mut head = Vec::with_capacity(TAR_BLOCK as usize);
+    (&mut decoded).take(TAR_BLOCK).read_to_end(&mut head)?;
+    let is_tar = head.len() == TAR_BLOCK as usize && &head[257..262] == b"ustar";
+
+    let decoded = BufReader::new(io::Cursor::new(head).chain(decoded));
+    if is_tar {
+        Ok(Box::new(open_tar(decoded)?))
+    } else {
+        Ok(Box::new(decoded))
+    }
+}
+
+/// Same as `open_records`, but starting `offset` bytes into the (decompressed) `Records.json`.
+/// Plain files are seeked, while compressed files and archives are decoded up to the offset.
+pub(crate) fn open_records_at(path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let magic = reader.fill_buf()?;
+
+    if [ZIP_MAGIC, GZIP_MAGIC, ZSTD_MAGIC, XZ_MAGIC].iter().any(|m| magic.starts_with(m)) {
+        let mut records = open_records(path)?;
+        io::copy(&mut (&mut records).take(offset), &mut io::sink())?;
+        return Ok(records);
+    }
+
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok(Box::new(reader))
+}
+
+// Takeout places Records.json in "Takeout/Location History (Timeline)/", although the
+// folder name has changed over the years, and is localised. So just look at the file name.
+fn is_records_json(name: &str) -> bool {
+    name.rsplit('/').next() == Some("Records.json")
+}
+
+fn records_not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no Records.json found in {}", path.display()),
+    )
+}
+
+fn open_zip(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut archive = ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+
+    let index = (0..archive.len())
+        .find(|&i| {
+            archive
+                .name_for_index(i)
+                .and_then(|name| name.ok())
+                .is_some_and(|name| is_records_json(&name))
+        })
+        .ok_or_else(|| records_not_found(path))?;
+
+    // the archive only hands out readers which borrow it, so note down where the entry data
+    // lives, and read it through a second handle to the file instead.
+    let entry = archive.by_index_raw(index).map_err(io::Error::other)?;
+    let method = entry.compression();
+    let size = entry.compressed_size();
+    let start = entry
+        .data_start()
+        .ok_or_else(|| io::Error::other("zip entry has no data offset"))?;
+    drop(entry);
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let data = BufReader::new(file).take(size);
+
+    match method {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(BufReader::new(DeflateDecoder::new(data)))),
+        method => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported zip compression method {}", method),
+        )),
+    }
+}
+
+// Walks the tar headers until Records.json is found, returning a reader over just that entry.
+// The tar crate's entries borrow the archive, which doesn't work for handing out a reader.
+fn open_tar<R: Read>(mut reader: R) -> io::Result<io::Take<R>> {
+    let mut block = [0u8; TAR_BLOCK as usize];
+    // GNU and PAX archives store long paths in a separate entry, before the real one
+    let mut long_name: Option<String> = None;
+
+    loop {
+        if reader.read_exact(&mut block).is_err() || block.iter().all(|&b| b == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no Records.json found in tar archive",
+            ));
+        }
+
+        let header = tar::Header::from_byte_slice(&block);
+        let size = header.entry_size()?;
+        let entry_type = header.entry_type();
+
+        if entry_type.is_gnu_longname() || entry_type.is_pax_local_extensions() {
+            let mut data = Vec::new();
+            (&mut reader).take(padded(size)).read_to_end(&mut data)?;
+            data.truncate(size as usize);
+
+            long_name = if entry_type.is_gnu_longname() {
+                Some(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string())
+            } else {
+                tar::PaxExtensions::new(&data)
+                    .filter_map(|ext| ext.ok())
+                    .find(|ext| ext.key() == Ok("path"))
+                    .and_then(|ext| ext.value().ok().map(String::from))
+            };
+            continue;
+        }
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => header.path()?.to_string_lossy().to_string(),
+        };
+
+        if is_records_json(&name) {
+            return Ok(reader.take(size));
+        }
+
+        io::copy(&mut (&mut reader).take(padded(size)), &mut io::sink())?;
+    }
+}
+
+fn padded(size: u64) -> u64 {
+    size.div_ceil(TAR_BLOCK) * TAR_BLOCK
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    #[test]
+    fn archives_and_compression() {
+        let records = br#"{"locations" : []}"#;
+        let name = "Takeout/Location History (Timeline)/Records.json";
+        let dir = std::env::temp_dir().join(format!("location_history_input_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // .zip, with another file before Records.json
+        let zip_path = dir.join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("Takeout/archive_browser.html", options).unwrap();
+        zip.write_all(b"<html></html>").unwrap();
+        zip.start_file(name, options).unwrap();
+        zip.write_all(records).unwrap();
+        zip.finish().unwrap();
+
+        // .tgz
+        let tgz_path = dir.join("takeout.tgz");
+        let gz = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tgz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(13);
+        tar.append_data(&mut header, "Takeout/archive_browser.html", &b"<html></html>"[..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(records.len() as u64);
+        tar.append_data(&mut header, name, &records[..]).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        // plain compressed files
+        let gz_path = dir.join("Records.json.gz");
+        let mut gz = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        gz.write_all(records).unwrap();
+        gz.finish().unwrap();
+
+        let zst_path = dir.join("Records.json.zst");
+        std::fs::write(&zst_path, zstd::encode_all(&records[..], 0).unwrap()).unwrap();
+
+        let xz_path = dir.join("Records.json.xz");
+        let mut xz = xz2::write::XzEncoder::new(std::fs::File::create(&xz_path).unwrap(), 6);
+        xz.write_all(records).unwrap();
+        xz.finish().unwrap();
+
+        for path in [zip_path, tgz_path, gz_path, zst_path, xz_path] {
+            let mut contents = Vec::new();
+            crate::open_records(&path).unwrap().read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, records);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
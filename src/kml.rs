@@ -0,0 +1,429 @@
+//! Reading and writing locations as KML, e.g. as exported by phone apps and Google Earth.
+//!
+//! When reading, locations come from two kinds of geometry:
+//!
+//! * `gx:Track`, where each `<gx:coord>` is paired up with the `<when>` at the same position.
+//!   If the track's `altitudeMode` is `clampToGround`, the altitudes are placeholders, and
+//!   aren't read.
+//! * `LineString`, which has no times of its own. The points are spread evenly across the
+//!   `TimeSpan` of the `Placemark` holding it, or all get its `TimeStamp`. Without either, such
+//!   as a route drawn in My Maps, the line string is skipped over.
+//!
+//! Other geometry, such as `Point` or `Polygon`, is skipped over too.
+//!
+//! When writing, each day becomes a `Folder`, holding a `Placemark` for each run of locations
+//! with the same top activity (see `TrackSplit::Activity`). The placemark's line has the colour
+//! of its activity in the CLI legend, and a `TimeSpan`, so Google Earth's time slider can replay
+//! the day. The points themselves are a `gx:Track`, so their times are kept as well.
+
+use chrono::{DateTime, FixedOffset};
+use colored::Color;
+use log::warn;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::segments::most_common_activity;
+use crate::xml::{escape_xml, format_utc, parse_time, start_offset};
+use crate::{collect_sorted, local_date, split_tracks, ActivityType, Error, Location, Locations, Result, TrackSplit};
+
+/// deserialize the tracks of a KML file into locations, sorted by timestamp
+pub fn deserialize_kml(from: &str) -> Result<Locations> {
+    collect_sorted(|tx| read_kml(from.as_bytes(), tx))
+}
+
+/// Reads a KML file and decodes the tracks on-the-fly, in the same manner as
+/// `deserialize_streaming`. Locations are sent in file order, one track at a time.
+///
+/// # Arguments
+///
+/// * `from` - The path to the `.kml` file.
+/// * `tx` - The `Sender` channel to send the decoded `Location` objects.
+pub fn deserialize_kml_streaming(from: PathBuf, tx: Sender<Location>) -> Result<()> {
+    let file = File::open::<PathBuf>(from)?;
+    read_kml(BufReader::new(file), tx)
+}
+
+// the text of the elements which make up a track, as written in the file
+#[derive(Default)]
+struct Track {
+    offset: u64,
+    when: Vec<String>,
+    coords: Vec<String>,
+    // the third value of each coord isn't an altitude to keep
+    clamped: bool,
+}
+
+// the times of a placemark, and the line strings within it, which can't be read until
+// the whole placemark has been
+#[derive(Default)]
+struct Placemark {
+    begin: Option<String>,
+    end: Option<String>,
+    when: Option<String>,
+    lines: Vec<(u64, String)>,
+}
+
+// sends the locations of a track, or of a line string, counting them in `index`
+struct Sink {
+    tx: Sender<Location>,
+    index: usize,
+}
+
+impl Sink {
+    // false once the receiver has hung up
+    fn send(&mut self, offset: u64, location: std::result::Result<Location, String>) -> Result<bool> {
+        let location = location.map_err(|e| Error::Record {
+            index: self.index,
+            offset: Some(offset),
+            source: e.into(),
+        })?;
+        self.index += 1;
+        Ok(self.tx.send(location).is_ok())
+    }
+}
+
+// `lon,lat[,alt]` in a LineString, or `lon lat [alt]` in a gx:Track
+fn parse_coord(s: &str, timestamp: DateTime<FixedOffset>) -> std::result::Result<Location, String> {
+    let parts: Vec<&str> = s.split([',', ' ']).filter(|p| !p.is_empty()).collect();
+    let number = |part: &str| part.parse::<f64>().map_err(|e| format!("invalid coordinates {:?}: {}", s, e));
+
+    let (longitude, latitude, altitude) = match parts.as_slice() {
+        [lon, lat] => (number(lon)?, number(lat)?, None),
+        [lon, lat, alt] => (number(lon)?, number(lat)?, Some(number(alt)?)),
+        _ => return Err(format!("invalid coordinates {:?}", s)),
+    };
+
+    Ok(Location {
+        timestamp,
+        latitude,
+        longitude,
+        altitude: altitude.map(|a| a.round() as i32),
+        ..Default::default()
+    })
+}
+
+fn send_track(sink: &mut Sink, track: Track) -> Result<bool> {
+    if track.when.len() != track.coords.len() {
+        let error = format!("gx:Track has {} <when> but {} <gx:coord>", track.when.len(), track.coords.len());
+        return sink.send(track.offset, Err(error));
+    }
+
+    for (when, coord) in track.when.iter().zip(&track.coords) {
+        let location = parse_time(when)
+            .ok_or_else(|| format!("invalid time {:?}", when))
+            .and_then(|timestamp| parse_coord(coord, timestamp))
+            .map(|location| if track.clamped { Location { altitude: None, ..location } } else { location });
+        if !sink.send(track.offset, location)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn send_lines(sink: &mut Sink, placemark: Placemark) -> Result<bool> {
+    let time = |s: &Option<String>| -> std::result::Result<Option<DateTime<FixedOffset>>, String> {
+        s.as_ref()
+            .map(|s| parse_time(s).ok_or_else(|| format!("invalid time {:?}", s)))
+            .transpose()
+    };
+
+    for (offset, coordinates) in placemark.lines {
+        let coords: Vec<&str> = coordinates.split_whitespace().collect();
+        let (begin, end) = match (time(&placemark.begin), time(&placemark.end), time(&placemark.when)) {
+            (Ok(Some(begin)), Ok(Some(end)), _) => (begin, end),
+            (Ok(None), Ok(None), Ok(Some(when))) => (when, when),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return sink.send(offset, Err(e)),
+            _ => {
+                warn!("skipping LineString at byte {}, which has no TimeSpan or TimeStamp", offset);
+                continue;
+            }
+        };
+
+        let step = (end - begin) / (coords.len() as i32 - 1).max(1);
+        for (i, coord) in coords.iter().enumerate() {
+            let location = parse_coord(coord, begin + step * i as i32);
+            if !sink.send(offset, location)? {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn read_kml<R: BufRead>(reader: R, tx: Sender<Location>) -> Result<()> {
+    let mut reader = Reader::from_reader(reader);
+    reader.config_mut().trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut sink = Sink { tx, index: 0 };
+    // the names of the elements the reader is within, innermost last
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut placemark: Option<Placemark> = None;
+    let mut track: Option<Track> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"Placemark" => placemark = Some(Placemark::default()),
+                    b"Track" => {
+                        let offset = start_offset(&reader, &e);
+                        track = Some(Track { offset, ..Default::default() });
+                    }
+                    b"coordinates" if path.last().is_some_and(|parent| parent == b"LineString") => {
+                        if let Some(placemark) = placemark.as_mut() {
+                            let offset = start_offset(&reader, &e);
+                            placemark.lines.push((offset, String::new()));
+                        }
+                    }
+                    _ => (),
+                }
+                path.push(name);
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+                let name = path.last().map(Vec::as_slice);
+                let parent = path.len().checked_sub(2).map(|i| path[i].as_slice());
+
+                if let Some(track) = track.as_mut() {
+                    match name {
+                        Some(b"when") => track.when.push(text),
+                        Some(b"coord") => track.coords.push(text),
+                        Some(b"altitudeMode") => track.clamped = text == "clampToGround",
+                        _ => (),
+                    }
+                } else if let Some(placemark) = placemark.as_mut() {
+                    match (parent, name) {
+                        (Some(b"LineString"), Some(b"coordinates")) => {
+                            if let Some((_, coordinates)) = placemark.lines.last_mut() {
+                                coordinates.push_str(&text);
+                            }
+                        }
+                        (Some(b"TimeSpan"), Some(b"begin")) => placemark.begin = Some(text),
+                        (Some(b"TimeSpan"), Some(b"end")) => placemark.end = Some(text),
+                        (Some(b"TimeStamp"), Some(b"when")) => placemark.when = Some(text),
+                        _ => (),
+                    }
+                }
+            }
+            Event::End(e) => {
+                path.pop();
+                match e.local_name().as_ref() {
+                    b"Track" => {
+                        if let Some(track) = track.take() {
+                            if !send_track(&mut sink, track)? {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    b"Placemark" => {
+                        if let Some(placemark) = placemark.take() {
+                            if !send_lines(&mut sink, placemark)? {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Event::Eof => return Ok(()),
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+/// Writes locations as KML, with a folder for each day, and a track for each activity within it.
+/// The locations are expected to be in chronological order.
+pub fn write_kml<W: Write>(mut writer: W, locations: &[Location]) -> Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">"#
+    )?;
+    writeln!(writer, "  <Document>")?;
+    writeln!(writer, "    <name>Location history</name>")?;
+
+    for day in split_tracks(locations, TrackSplit::Day) {
+        writeln!(writer, "    <Folder>")?;
+        writeln!(writer, "      <name>{}</name>", local_date(&day[0]))?;
+        write_time_span(&mut writer, day, "      ")?;
+        for track in split_tracks(day, TrackSplit::Activity) {
+            write_track(&mut writer, track)?;
+        }
+        writeln!(writer, "    </Folder>")?;
+    }
+
+    writeln!(writer, "  </Document>")?;
+    writeln!(writer, "</kml>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes locations as KMZ, i.e. the output of `write_kml` in a zip archive as `doc.kml`.
+pub fn write_kmz<W: Write + Seek>(writer: W, locations: &[Location]) -> Result<()> {
+    let mut zip = ZipWriter::new(writer);
+    let options = zip::write::SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("doc.kml", options).map_err(io::Error::other)?;
+    write_kml(&mut zip, locations)?;
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn write_time_span<W: Write>(writer: &mut W, track: &[Location], indent: &str) -> Result<()> {
+    let (first, last) = (&track[0], &track[track.len() - 1]);
+    writeln!(writer, "{}<TimeSpan>", indent)?;
+    writeln!(writer, "{}  <begin>{}</begin>", indent, format_utc(first.timestamp))?;
+    writeln!(writer, "{}  <end>{}</end>", indent, format_utc(last.timestamp))?;
+    writeln!(writer, "{}</TimeSpan>", indent)?;
+    Ok(())
+}
+
+fn write_track<W: Write>(writer: &mut W, track: &[Location]) -> Result<()> {
+    let activity_type = most_common_activity(track).unwrap_or(ActivityType::UNKNOWN);
+
+    writeln!(writer, "      <Placemark>")?;
+    writeln!(writer, "        <name>{}</name>", escape_xml(activity_type.as_str()))?;
+    write_time_span(writer, track, "        ")?;
+    writeln!(writer, "        <Style>")?;
+    writeln!(writer, "          <LineStyle>")?;
+    writeln!(writer, "            <color>{}</color>", kml_color(activity_type.legend_color()))?;
+    writeln!(writer, "            <width>4</width>")?;
+    writeln!(writer, "          </LineStyle>")?;
+    writeln!(writer, "        </Style>")?;
+    writeln!(writer, "        <gx:Track>")?;
+    // a gx:coord always has an altitude, but it is only used if it is known, and isn't read
+    // back otherwise. the schema puts this before the times and coordinates
+    let altitude_mode = if track.iter().all(|l| l.altitude.is_some()) { "absolute" } else { "clampToGround" };
+    writeln!(writer, "          <altitudeMode>{}</altitudeMode>", altitude_mode)?;
+    for location in track {
+        writeln!(writer, "          <when>{}</when>", format_utc(location.timestamp))?;
+    }
+    for location in track {
+        writeln!(
+            writer,
+            "          <gx:coord>{:.7} {:.7} {}</gx:coord>",
+            location.longitude,
+            location.latitude,
+            location.altitude.unwrap_or(0)
+        )?;
+    }
+    writeln!(writer, "        </gx:Track>")?;
+    writeln!(writer, "      </Placemark>")?;
+    Ok(())
+}
+
+// KML colours are opaque `aabbggrr`, and the terminal colours are those of xterm
+fn kml_color(color: Color) -> String {
+    let (r, g, b) = match color {
+        Color::Black => (0, 0, 0),
+        Color::Red => (205, 0, 0),
+        Color::Green => (0, 205, 0),
+        Color::Yellow => (205, 205, 0),
+        Color::Blue => (0, 0, 238),
+        Color::Magenta => (205, 0, 205),
+        Color::Cyan => (0, 205, 205),
+        Color::White => (229, 229, 229),
+        Color::BrightBlack => (127, 127, 127),
+        Color::BrightRed => (255, 0, 0),
+        Color::BrightGreen => (0, 255, 0),
+        Color::BrightYellow => (255, 255, 0),
+        Color::BrightBlue => (92, 92, 255),
+        Color::BrightMagenta => (255, 0, 255),
+        Color::BrightCyan => (0, 255, 255),
+        Color::BrightWhite => (255, 255, 255),
+        Color::TrueColor { r, g, b } => (r, g, b),
+    };
+    format!("ff{:02x}{:02x}{:02x}", b, g, r)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, ActivityType};
+
+    #[test]
+    fn kml_tracks() {
+        let test_data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Placemark>
+      <gx:Track>
+        <when>2024-01-01T09:00:00Z</when>
+        <when>2024-01-01T09:01:00Z</when>
+        <gx:coord>145.0703899 -37.8224062 52</gx:coord>
+        <gx:coord>145.0704 -37.8225 53</gx:coord>
+      </gx:Track>
+    </Placemark>
+    <Placemark>
+      <LineString>
+        <coordinates>
+          145.1,-37.8,0 145.2,-37.9,0 145.3,-38.0,0
+        </coordinates>
+      </LineString>
+      <TimeSpan><begin>2024-01-01T10:00:00Z</begin><end>2024-01-01T10:10:00Z</end></TimeSpan>
+    </Placemark>
+    <Placemark>
+      <Point><coordinates>145.0,-37.0,0</coordinates></Point>
+    </Placemark>
+    <Placemark>
+      <name>a planned route</name>
+      <LineString><coordinates>145.0,-37.0,0 145.1,-37.1,0</coordinates></LineString>
+    </Placemark>
+  </Document>
+</kml>"#;
+
+        let locations = crate::deserialize_kml(test_data).unwrap();
+        assert_eq!(locations.len(), 5);
+        assert_eq!(locations[0].latitude, -37.8224062);
+        assert_eq!(locations[1].altitude, Some(53));
+        // the line string's points are spread across its time span
+        assert_eq!(locations[3].timestamp.to_rfc3339(), "2024-01-01T10:05:00+00:00");
+        assert_eq!(locations[4].longitude, 145.3);
+
+        let broken = test_data.replace("<gx:coord>145.0704 -37.8225 53</gx:coord>", "");
+        assert!(crate::deserialize_kml(&broken).is_err());
+        let broken = test_data.replace("<end>2024-01-01T10:10:00Z</end>", "<end>later</end>");
+        assert!(crate::deserialize_kml(&broken).is_err());
+    }
+
+    #[test]
+    fn kml_export() {
+        let mut locations = fixtures::locations();
+        locations.push(fixtures::at(&locations[1], "2024-01-02T09:00:00+10:00"));
+        locations[1] = fixtures::with_activity(locations[1].clone(), ActivityType::IN_CAR);
+        locations[1].altitude = Some(53);
+
+        let mut out = Vec::new();
+        crate::write_kml(&mut out, &locations).unwrap();
+        let kml = String::from_utf8(out).unwrap();
+        assert_eq!(kml.matches("<Folder>").count(), 2);
+        assert_eq!(kml.matches("<Placemark>").count(), 3);
+        // walking is bright green, and the car bright cyan, as in the legend
+        assert!(kml.contains("<name>WALKING</name>"));
+        assert!(kml.contains("<color>ff00ff00</color>"));
+        assert!(kml.contains("<color>ffffff00</color>"));
+        assert!(kml.contains("<begin>2023-12-31T23:12:00.123Z</begin>"));
+        assert!(kml.contains("<gx:Track>\n          <altitudeMode>absolute</altitudeMode>\n          <when>"));
+
+        // the tracks read back with the same times and places
+        let read = crate::deserialize_kml(&kml).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[1].timestamp, locations[1].timestamp);
+        assert_eq!((read[1].latitude, read[1].altitude), (-37.82241, Some(53)));
+        // and the location without an altitude doesn't gain one
+        assert_eq!(read[2].altitude, None);
+
+        let mut kmz = std::io::Cursor::new(Vec::new());
+        crate::write_kmz(&mut kmz, &locations).unwrap();
+        let mut archive = zip::ZipArchive::new(kmz).unwrap();
+        let mut doc = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("doc.kml").unwrap(), &mut doc).unwrap();
+        assert_eq!(doc, kml);
+    }
+}
@@ -5,18 +5,20 @@ use chrono::{DateTime, FixedOffset};
 use serde_derive::{Deserialize, Serialize};
 
 extern crate prettytable;
-use colored::{Colorize, ColoredString};
+use colored::{Color, Colorize, ColoredString};
 use prettytable::row;
 
 extern crate struson;
 use std::collections::{HashSet, HashMap};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::cmp::max;
 use struson::json_path;
-use struson::reader::{JsonReader, JsonStreamReader};
+use struson::reader::json_path::JsonPath;
+use struson::reader::{JsonReader, JsonStreamReader, ReaderError};
+use struson::serde::DeserializerError;
 
 use glob_match::glob_match;
 
@@ -25,6 +27,40 @@ use log::{debug, error, info, log_enabled, Level};
 
 use geo::{Coord, HaversineDistance, Point};
 
+mod cache;
+mod columnar;
+mod compact;
+mod delimited;
+mod error;
+mod geojson;
+mod gpx;
+mod index;
+mod kml;
+mod input;
+mod parallel;
+mod reader;
+mod segments;
+mod sqlite;
+mod timeline;
+mod writer;
+mod xml;
+pub use cache::{cache_path, read_cache, write_cache};
+pub use columnar::{parquet_schema, write_parquet};
+pub use compact::{CompactActivities, CompactActivity, CompactLocation, CompactLocations};
+pub use delimited::{deserialize_csv, deserialize_csv_streaming, write_csv, CsvColumn, CsvOptions};
+pub use error::{Error, Result, SkipReport, SkippedRecord};
+pub use geojson::{write_geojson, GeoJsonMode};
+pub use gpx::{deserialize_gpx, deserialize_gpx_streaming, write_gpx};
+pub use index::{deserialize_streaming_range, index_path, MonthIndex, MonthOffset};
+pub use input::open_records;
+pub use kml::{deserialize_kml, deserialize_kml_streaming, write_kml, write_kmz};
+pub use parallel::deserialize_streaming_parallel;
+pub use reader::LocationReader;
+pub use segments::{local_date, split_tracks, TrackSplit, TRIP_GAP_SECONDS};
+pub use sqlite::{write_sqlite, GRID_CELLS_PER_DEGREE};
+pub use timeline::{deserialize_timeline, deserialize_timeline_streaming, is_timeline};
+pub use writer::{serialize, serialize_to_writer};
+
 /// group of locations
 pub type Locations = Vec<Location>;
 
@@ -42,20 +78,20 @@ pub trait LocationsExt {
     fn sort_chronological(&mut self);
 
     /// remove locations that are offset more than 300km/h from last location
-    fn filter_outliers(self) -> Locations;
+    fn filter_outliers(self) -> Self;
 
     // filter by activity, where the highest-confidence activity is the one that is passed as argument
     // uses glob_match to compare the activity type - so you can write:
     // {ON_FOOT,STILL} # for either case
     // ON_*            # for whenever we are on something
     // IN_*            # for whenever we are IN something
-    fn filter_by_activity(self, activity: String) -> Locations;
+    fn filter_by_activity(self, activity: String) -> Self;
 
     // retrieves the unique set of activity types in the data
     fn list_activities(&self) -> Vec<String>;
 
     // filters to points within a distance of a point
-    fn filter_by_distance(self, point: Point<f64>, distance: f64) -> Locations;
+    fn filter_by_distance(self, point: Point<f64>, distance: f64) -> Self;
 }
 
 impl LocationsExt for Locations {
@@ -94,14 +130,15 @@ impl LocationsExt for Locations {
     }
 
     fn filter_outliers(self) -> Locations {
-        let mut tmp: Vec<Location> = vec![self[0].clone()];
+        let mut tmp: Vec<Location> = Vec::new();
 
+        // the first location is always kept, and each after it is compared with the last kept
         for location in self.into_iter() {
-            if let Some(speed) = location.speed_kmh(&tmp[tmp.len() - 1]) {
-                if speed < 300.0 {
-                    tmp.push(location);
-                }
-            } else {
+            let keep = match tmp.last() {
+                Some(last) => location.speed_kmh(last).is_none_or(|speed| speed < 300.0),
+                None => true,
+            };
+            if keep {
                 tmp.push(location);
             }
         }
@@ -117,10 +154,10 @@ impl LocationsExt for Locations {
             // iterate through all activities recorded at this location
             if let Some(activities) = &location.activities {
                 for activity in activities.into_iter() {
-                    // check if the highest-confidence activity is the one we want
+                    // check if the highest-confidence activity is the one we want, or a kind of it
                     if let Some(activity) = activity.activities.iter().max_by_key(|x| x.confidence)
                     {
-                        if glob_match(&activity_type, &activity.activity_type) {
+                        if activity.activity_type.matches(&activity_type) {
                             tmp.push(location.clone());
                             break;
                         }
@@ -141,7 +178,7 @@ impl LocationsExt for Locations {
             if let Some(activities) = &location.activities {
                 for activity in activities.into_iter() {
                     for act in activity.activities.iter() {
-                        activities_set.insert(act.activity_type.clone());
+                        activities_set.insert(act.activity_type.as_str().to_string());
                     }
                 }
             }
@@ -164,58 +201,173 @@ impl LocationsExt for Locations {
 }
 
 /// deserialize location history
-pub fn deserialize(from: &str) -> Locations {
-    #[derive(Deserialize)]
-    struct LocationList {
-        locations: Vec<Location>,
-    }
+pub fn deserialize(from: &str) -> Result<Locations> {
+    let mut locations = LocationReader::new(from.as_bytes()).collect::<Result<Locations>>()?;
 
-    let mut deserialized: LocationList = serde_json::from_str(from).expect("Failed to deserialize");
+    locations.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Ok(locations)
+}
 
-    deserialized
-        .locations
-        .sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-    deserialized.locations
+// runs a reader which sends locations down a channel, for the `deserialize_*` of each format,
+// and collects what it sends in chronological order
+pub(crate) fn collect_sorted<F>(read: F) -> Result<Locations>
+where
+    F: FnOnce(Sender<Location>) -> Result<()>,
+{
+    let (tx, rx) = std::sync::mpsc::channel();
+    read(tx)?;
+
+    let mut locations: Locations = rx.into_iter().collect();
+    locations.sort_chronological();
+    Ok(locations)
 }
 
 /// Reads a `Records.json` file and decodes the data on-the-fly.
 /// The file is expected to contain a single large array of `Location` objects
 /// under a 'locations' key.
 ///
+/// The path may also point at a Takeout `.zip` or `.tgz` archive, in which case
+/// the `Records.json` inside it is decompressed as it is read. Likewise for
+/// gzip, zstd or xz compressed files.
+///
 /// This function sends each decoded `Location` object to the provided
-/// MPSC channel as soon as it is decoded.
+/// MPSC channel as soon as it is decoded. Reading stops at the first record
+/// which can't be decoded, and the error is returned.
+///
+/// Every record is sent, so the whole file is always read. To read a date range,
+/// use `deserialize_streaming_range`, which seeks to the start of it with the
+/// `MonthIndex` next to the file, if there is one.
 ///
 /// It is recommended to call this function from a separate thread, as it will
 /// block until the entire file has been read.
 ///
 /// # Arguments
 ///
-/// * `from` - The path to the `Records.json` file.
+/// * `from` - The path to the `Records.json` file, or a Takeout archive containing it.
 /// * `tx` - The `Sender` channel to send the decoded `Location` objects.
-pub fn deserialize_streaming(from: PathBuf, tx: Sender<Location>) {
-    let file = File::open::<PathBuf>(from).unwrap();
-    let reader = BufReader::new(file);
+pub fn deserialize_streaming(from: PathBuf, tx: Sender<Location>) -> Result<()> {
+    send_locations(&mut LocationReader::open(&from)?, tx)
+}
+
+/// deserialize location history, keeping the fields `Location` doesn't model in `Location::extra`.
+/// unlike `deserialize`, the locations are left in file order, so that a filtered subset
+/// can be written back out with `serialize` exactly as it was read.
+pub fn deserialize_lossless(from: &str) -> Result<Locations> {
+    LocationReader::new(from.as_bytes()).lossless(true).collect()
+}
 
+/// Same as `deserialize_streaming`, but keeps the fields `Location` doesn't model in `Location::extra`.
+/// This costs more memory, so is only worth it when the locations are going to be written back out.
+pub fn deserialize_streaming_lossless(from: PathBuf, tx: Sender<Location>) -> Result<()> {
+    send_locations(&mut LocationReader::open(&from)?.lossless(true), tx)
+}
+
+/// Same as `deserialize_streaming`, but records which can't be decoded are skipped,
+/// rather than stopping the read. The returned `SkipReport` lists what was skipped, and why.
+///
+/// Records which are valid JSON, but aren't a valid `Location` (e.g. a missing `latitudeE7`)
+/// are stepped over. If the JSON itself is broken, e.g. the file is truncated, there is no way
+/// to find the start of the next record, so reading stops there. The broken record is the last
+/// one in the report.
+pub fn deserialize_streaming_lenient(from: PathBuf, tx: Sender<Location>) -> Result<SkipReport> {
+    let mut reader = LocationReader::open(&from)?.lenient(true);
+    send_locations(&mut reader, tx)?;
+    Ok(reader.into_skip_report())
+}
+
+// sends locations until the receiver hangs up
+fn send_locations<R: Read>(reader: &mut LocationReader<R>, tx: Sender<Location>) -> Result<()> {
+    for location in reader {
+        if tx.send(location?).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// decodes each entry of the array at `path`, handing it to `f` until `f` returns false
+fn read_array<R, T, F>(reader: R, path: &JsonPath, mut f: F) -> Result<()>
+where
+    R: Read,
+    T: serde::de::DeserializeOwned,
+    F: FnMut(T) -> bool,
+{
     let mut json_reader = JsonStreamReader::new(reader);
 
-    json_reader.seek_to(&json_path!["locations"]).unwrap();
+    json_reader.seek_to(path)?;
 
-    json_reader.begin_array().unwrap();
+    json_reader.begin_array()?;
 
-    while json_reader.has_next().unwrap() {
-        let location: Location = json_reader.deserialize_next().unwrap();
-        match tx.send(location) {
-            Ok(_) => {}
-            Err(_) => break,
+    let mut index = 0;
+    while json_reader.has_next()? {
+        if !f(next_record(&mut json_reader, index)?) {
+            break;
         }
+        index += 1;
     }
+
+    Ok(())
+}
+
+// decodes the next array entry, noting down where it was if it can't be decoded. broken JSON
+// isn't a problem with the record itself, so it is an `Error::Json` instead
+pub(crate) fn next_record<R, T>(json_reader: &mut JsonStreamReader<R>, index: usize) -> Result<T>
+where
+    R: Read,
+    T: serde::de::DeserializeOwned,
+{
+    let offset = json_reader.current_position(false).data_pos;
+    json_reader.deserialize_next().map_err(|e| match e {
+        DeserializerError::ReaderError(e @ (ReaderError::SyntaxError(_) | ReaderError::IoError { .. })) => Error::Json(e),
+        e => Error::Record {
+            index,
+            offset,
+            source: e.into(),
+        },
+    })
+}
+
+/// deserialize a Semantic Location History monthly file, e.g. `2024_JANUARY.json`
+pub fn deserialize_semantic(from: &str) -> Result<Vec<TimelineObject>> {
+    let mut objects: Vec<TimelineObject> = Vec::new();
+    read_array(from.as_bytes(), &json_path!["timelineObjects"], |object| {
+        objects.push(object);
+        true
+    })?;
+
+    objects.sort_by_key(|o| o.duration().start_timestamp);
+    Ok(objects)
+}
+
+/// Reads a Semantic Location History monthly file (`Semantic Location History/YYYY/YYYY_MONTH.json`)
+/// and decodes the data on-the-fly, in the same manner as `deserialize_streaming`.
+/// The file is expected to contain a single array of `TimelineObject` entries
+/// under a 'timelineObjects' key.
+///
+/// # Arguments
+///
+/// * `from` - The path to the monthly JSON file.
+/// * `tx` - The `Sender` channel to send the decoded `TimelineObject` entries.
+pub fn deserialize_semantic_streaming(from: PathBuf, tx: Sender<TimelineObject>) -> Result<()> {
+    let file = File::open::<PathBuf>(from)?;
+    let reader = BufReader::new(file);
+
+    read_array(reader, &json_path!["timelineObjects"], |object| {
+        tx.send(object).is_ok()
+    })
 }
 
 // make an activity type Enum, which will be useful for color-coding and filtering things by activity
-#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 #[allow(non_camel_case_types)]
 pub enum ActivityType {
     IN_VEHICLE,
+    IN_ROAD_VEHICLE,
+    IN_RAIL_VEHICLE,
+    IN_FOUR_WHEELER_VEHICLE,
+    IN_TWO_WHEELER_VEHICLE,
+    IN_CAR,
+    IN_BUS,
     EXITING_VEHICLE,
     ON_BICYCLE,
     ON_FOOT,
@@ -224,36 +376,98 @@ pub enum ActivityType {
     TILTING,
     UNKNOWN,
     WALKING,
+    /// a type this crate doesn't know about, as it was written in the file
+    Other(String),
+}
+
+impl ActivityType {
+    /// the name used in the location history files, e.g. `IN_VEHICLE`
+    pub fn as_str(&self) -> &str {
+        match self {
+            ActivityType::IN_VEHICLE => "IN_VEHICLE",
+            ActivityType::IN_ROAD_VEHICLE => "IN_ROAD_VEHICLE",
+            ActivityType::IN_RAIL_VEHICLE => "IN_RAIL_VEHICLE",
+            ActivityType::IN_FOUR_WHEELER_VEHICLE => "IN_FOUR_WHEELER_VEHICLE",
+            ActivityType::IN_TWO_WHEELER_VEHICLE => "IN_TWO_WHEELER_VEHICLE",
+            ActivityType::IN_CAR => "IN_CAR",
+            ActivityType::IN_BUS => "IN_BUS",
+            ActivityType::EXITING_VEHICLE => "EXITING_VEHICLE",
+            ActivityType::ON_BICYCLE => "ON_BICYCLE",
+            ActivityType::ON_FOOT => "ON_FOOT",
+            ActivityType::RUNNING => "RUNNING",
+            ActivityType::STILL => "STILL",
+            ActivityType::TILTING => "TILTING",
+            ActivityType::UNKNOWN => "UNKNOWN",
+            ActivityType::WALKING => "WALKING",
+            ActivityType::Other(name) => name,
+        }
+    }
+
+    /// the more general type this is a kind of, e.g. `IN_ROAD_VEHICLE` for `IN_CAR`
+    pub fn parent(&self) -> Option<ActivityType> {
+        match self {
+            ActivityType::IN_ROAD_VEHICLE | ActivityType::IN_RAIL_VEHICLE => Some(ActivityType::IN_VEHICLE),
+            ActivityType::IN_FOUR_WHEELER_VEHICLE | ActivityType::IN_TWO_WHEELER_VEHICLE => Some(ActivityType::IN_ROAD_VEHICLE),
+            ActivityType::IN_CAR | ActivityType::IN_BUS => Some(ActivityType::IN_FOUR_WHEELER_VEHICLE),
+            ActivityType::WALKING | ActivityType::RUNNING => Some(ActivityType::ON_FOOT),
+            _ => None,
+        }
+    }
+
+    /// true if this is `other`, or a kind of it, e.g. `IN_CAR` is a kind of `IN_VEHICLE`
+    pub fn is_a(&self, other: &ActivityType) -> bool {
+        self == other || self.parent().is_some_and(|parent| parent.is_a(other))
+    }
+
+    /// true if the name of this type, or of any type it is a kind of, matches the glob
+    pub fn matches(&self, pattern: &str) -> bool {
+        glob_match(pattern, self.as_str()) || self.parent().is_some_and(|parent| parent.matches(pattern))
+    }
+}
+
+// activity types are written as their names, so unknown ones survive a round trip
+impl serde::Serialize for ActivityType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ActivityType {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<ActivityType, D::Error> {
+        let name: String = serde::Deserialize::deserialize(de)?;
+        Ok(name.into())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Activity {
     #[serde(rename = "type")]
-    pub activity_type: String,
+    pub activity_type: ActivityType,
     pub confidence: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Activities {
-    #[serde(deserialize_with = "parse_timestamp")]
-    /// timestamp this location was sampled at
-    pub timestamp: DateTime<FixedOffset>,
-
     /// activities list
-    #[serde(rename = "activity")]
+    #[serde(rename = "activity", alias = "activities")]
     pub activities: Vec<Activity>,
+
+    #[serde(
+        flatten,
+        deserialize_with = "parse_record_timestamp",
+        serialize_with = "serialize_record_timestamp"
+    )]
+    /// timestamp this location was sampled at
+    pub timestamp: DateTime<FixedOffset>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 /// Location sample parsed from LocationHistory.json
 pub struct Location {
-    #[serde(deserialize_with = "parse_timestamp")]
     /// timestamp this location was sampled at
     pub timestamp: DateTime<FixedOffset>,
-    #[serde(rename = "latitudeE7", deserialize_with = "parse_location")]
     /// latitude, converted from lat E7
     pub latitude: f64,
-    #[serde(rename = "longitudeE7", deserialize_with = "parse_location")]
     /// longitude, converted from long E7
     pub longitude: f64,
     /// accuracy of location sample in meters
@@ -261,8 +475,383 @@ pub struct Location {
     /// altitude in meters, if available
     pub altitude: Option<i32>,
 
-    #[serde(rename = "activity")]
     pub activities: Option<Vec<Activities>>,
+
+    /// speed reported by the device, in meters per second
+    pub velocity: Option<i32>,
+    /// direction of travel reported by the device, in degrees east of true north
+    pub heading: Option<i32>,
+    /// accuracy of the altitude in meters
+    pub vertical_accuracy: Option<i32>,
+    /// how the location was determined, e.g. GPS, WIFI, CELL
+    pub source: Option<String>,
+    /// identifies the device which recorded this location
+    pub device_tag: Option<i64>,
+    /// e.g. ANDROID, IOS
+    pub platform_type: Option<String>,
+    /// e.g. PHONE, TABLET
+    pub form_factor: Option<String>,
+    pub os_level: Option<i32>,
+    pub battery_charging: Option<bool>,
+    /// time the location was received by Google
+    pub server_timestamp: Option<DateTime<FixedOffset>>,
+    /// time according to the device clock
+    pub device_timestamp: Option<DateTime<FixedOffset>>,
+    /// Google Maps place the device was at, if known
+    pub place_id: Option<String>,
+    /// wifi access points visible when the location was recorded
+    pub active_wifi_scan: Option<WifiScan>,
+
+    /// fields not modelled above, in their original order.
+    /// only kept when reading with `deserialize_lossless` or `deserialize_streaming_lossless`
+    pub extra: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+// the keys of a Location in Records.json, for everything but its timestamp, which is read by
+// `TimedLocation`. remote, so that Location's own Deserialize impl can be written by hand
+#[derive(Deserialize)]
+#[serde(remote = "Location")]
+struct LocationDef {
+    // read by `TimedLocation`
+    #[serde(skip_deserializing)]
+    timestamp: DateTime<FixedOffset>,
+    #[serde(rename = "latitudeE7", deserialize_with = "parse_location")]
+    latitude: f64,
+    #[serde(rename = "longitudeE7", deserialize_with = "parse_location")]
+    longitude: f64,
+    accuracy: Option<i32>,
+    altitude: Option<i32>,
+
+    // very old exports misspell this as 'activitys'
+    #[serde(rename = "activity", alias = "activitys")]
+    activities: Option<Vec<Activities>>,
+
+    velocity: Option<i32>,
+    heading: Option<i32>,
+    #[serde(rename = "verticalAccuracy")]
+    vertical_accuracy: Option<i32>,
+    source: Option<String>,
+    #[serde(rename = "deviceTag")]
+    device_tag: Option<i64>,
+    #[serde(rename = "platformType")]
+    platform_type: Option<String>,
+    #[serde(rename = "formFactor")]
+    form_factor: Option<String>,
+    #[serde(rename = "osLevel")]
+    os_level: Option<i32>,
+    #[serde(rename = "batteryCharging")]
+    battery_charging: Option<bool>,
+    #[serde(rename = "serverTimestamp", default, deserialize_with = "parse_optional_timestamp")]
+    server_timestamp: Option<DateTime<FixedOffset>>,
+    #[serde(rename = "deviceTimestamp", default, deserialize_with = "parse_optional_timestamp")]
+    device_timestamp: Option<DateTime<FixedOffset>>,
+    #[serde(rename = "placeId")]
+    place_id: Option<String>,
+    #[serde(rename = "activeWifiScan")]
+    active_wifi_scan: Option<WifiScan>,
+
+    #[serde(skip)]
+    extra: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+// reads a Location, with its timestamp from either of the keys it may be under. the timestamp
+// can't be a flattened field of LocationDef itself, as then LocationDef couldn't be flattened
+// into LosslessLocation, without every field ending up in `extra` too
+#[derive(Deserialize)]
+struct TimedLocation {
+    #[serde(flatten, deserialize_with = "parse_record_timestamp")]
+    timestamp: DateTime<FixedOffset>,
+    #[serde(flatten, with = "LocationDef")]
+    location: Location,
+}
+
+impl<'de> serde::Deserialize<'de> for Location {
+    fn deserialize<D>(deserializer: D) -> Result<Location, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let timed = TimedLocation::deserialize(deserializer)?;
+        Ok(Location {
+            timestamp: timed.timestamp,
+            ..timed.location
+        })
+    }
+}
+
+// reads a Location, keeping hold of any unknown fields
+#[derive(Deserialize)]
+struct LosslessLocation {
+    #[serde(flatten, deserialize_with = "parse_record_timestamp")]
+    timestamp: DateTime<FixedOffset>,
+    #[serde(flatten, with = "LocationDef")]
+    location: Location,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<LosslessLocation> for Location {
+    fn from(lossless: LosslessLocation) -> Location {
+        Location {
+            timestamp: lossless.timestamp,
+            extra: Some(lossless.extra).filter(|extra| !extra.is_empty()),
+            ..lossless.location
+        }
+    }
+}
+
+// written by hand, rather than derived, so the field order follows Records.json
+// and unknown fields end up before the timestamp, where Google puts them.
+impl serde::Serialize for Location {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+
+        // optional fields are left out, rather than written as null
+        macro_rules! optional {
+            ($key:expr, $value:expr) => {
+                if let Some(value) = &$value {
+                    map.serialize_entry($key, value)?;
+                }
+            };
+        }
+
+        map.serialize_entry("latitudeE7", &to_e7(self.latitude))?;
+        map.serialize_entry("longitudeE7", &to_e7(self.longitude))?;
+        optional!("accuracy", self.accuracy);
+        optional!("velocity", self.velocity);
+        optional!("heading", self.heading);
+        optional!("altitude", self.altitude);
+        optional!("verticalAccuracy", self.vertical_accuracy);
+        optional!("activity", self.activities);
+        optional!("source", self.source);
+        optional!("deviceTag", self.device_tag);
+        optional!("platformType", self.platform_type);
+        optional!("activeWifiScan", self.active_wifi_scan);
+        optional!("osLevel", self.os_level);
+        optional!("serverTimestamp", self.server_timestamp.map(format_timestamp));
+        optional!("deviceTimestamp", self.device_timestamp.map(format_timestamp));
+        optional!("batteryCharging", self.battery_charging);
+        optional!("formFactor", self.form_factor);
+        optional!("placeId", self.place_id);
+
+        if let Some(extra) = &self.extra {
+            for (key, value) in extra {
+                map.serialize_entry(key, value)?;
+            }
+        }
+
+        map.serialize_entry("timestamp", &format_timestamp(self.timestamp))?;
+        map.end()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WifiScan {
+    #[serde(rename = "accessPoints", default)]
+    pub access_points: Vec<AccessPoint>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccessPoint {
+    /// MAC address, as a decimal number
+    pub mac: String,
+    /// signal strength in dBm
+    pub strength: i32,
+    #[serde(rename = "frequencyMhz", skip_serializing_if = "Option::is_none")]
+    pub frequency_mhz: Option<i32>,
+    #[serde(rename = "isConnected", skip_serializing_if = "Option::is_none")]
+    pub is_connected: Option<bool>,
+}
+
+/// a single entry of a Semantic Location History `YYYY_MONTH.json` file.
+/// each entry holds exactly one of a `placeVisit` or an `activitySegment`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum TimelineObject {
+    #[serde(rename = "placeVisit")]
+    PlaceVisit(PlaceVisit),
+    #[serde(rename = "activitySegment")]
+    ActivitySegment(ActivitySegment),
+}
+
+/// start and end time of a place visit or activity segment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineDuration {
+    #[serde(
+        alias = "startTimestampMs",
+        deserialize_with = "parse_timestamp",
+        serialize_with = "serialize_timestamp"
+    )]
+    pub start_timestamp: DateTime<FixedOffset>,
+    #[serde(
+        alias = "endTimestampMs",
+        deserialize_with = "parse_timestamp",
+        serialize_with = "serialize_timestamp"
+    )]
+    pub end_timestamp: DateTime<FixedOffset>,
+}
+
+/// a place, as referenced by visits and by the ends of activity segments.
+/// Google omits most of these fields for unconfirmed segment endpoints.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticLocation {
+    #[serde(
+        rename = "latitudeE7",
+        default,
+        deserialize_with = "parse_optional_location",
+        serialize_with = "serialize_optional_location"
+    )]
+    pub latitude: Option<f64>,
+    #[serde(
+        rename = "longitudeE7",
+        default,
+        deserialize_with = "parse_optional_location",
+        serialize_with = "serialize_optional_location"
+    )]
+    pub longitude: Option<f64>,
+    pub place_id: Option<String>,
+    pub address: Option<String>,
+    pub name: Option<String>,
+    /// e.g. TYPE_HOME, TYPE_WORK
+    pub semantic_type: Option<String>,
+    pub location_confidence: Option<f64>,
+    pub calibrated_probability: Option<f64>,
+}
+
+/// time spent at a single place, as segmented by Google
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaceVisit {
+    pub location: SemanticLocation,
+    pub duration: TimelineDuration,
+    pub place_confidence: Option<String>,
+    #[serde(
+        rename = "centerLatE7",
+        default,
+        deserialize_with = "parse_optional_location",
+        serialize_with = "serialize_optional_location"
+    )]
+    pub center_latitude: Option<f64>,
+    #[serde(
+        rename = "centerLngE7",
+        default,
+        deserialize_with = "parse_optional_location",
+        serialize_with = "serialize_optional_location"
+    )]
+    pub center_longitude: Option<f64>,
+    pub visit_confidence: Option<i32>,
+    #[serde(default)]
+    pub other_candidate_locations: Vec<SemanticLocation>,
+    pub edit_confirmation_status: Option<String>,
+    pub place_visit_type: Option<String>,
+    pub place_visit_importance: Option<String>,
+    /// visits nested inside this one, e.g. a shop inside a shopping centre
+    #[serde(default)]
+    pub child_visits: Vec<PlaceVisit>,
+}
+
+/// a trip between two places, as segmented by Google
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivitySegment {
+    pub start_location: SemanticLocation,
+    pub end_location: SemanticLocation,
+    pub duration: TimelineDuration,
+    /// distance travelled in meters
+    pub distance: Option<i32>,
+    /// the most likely activity, e.g. IN_PASSENGER_VEHICLE
+    pub activity_type: Option<String>,
+    /// HIGH, MEDIUM or LOW
+    pub confidence: Option<String>,
+    /// all candidate activities, in descending order of probability
+    #[serde(default)]
+    pub activities: Vec<ActivityCandidate>,
+    pub waypoint_path: Option<WaypointPath>,
+    pub simplified_raw_path: Option<SimplifiedRawPath>,
+    pub edit_confirmation_status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityCandidate {
+    pub activity_type: String,
+    /// probability as a percentage
+    pub probability: f64,
+}
+
+/// road-snapped path of an activity segment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WaypointPath {
+    #[serde(default)]
+    pub waypoints: Vec<Waypoint>,
+    pub source: Option<String>,
+    pub distance_meters: Option<f64>,
+    pub travel_mode: Option<String>,
+    pub confidence: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Waypoint {
+    #[serde(
+        rename = "latE7",
+        deserialize_with = "parse_location",
+        serialize_with = "serialize_location"
+    )]
+    pub latitude: f64,
+    #[serde(
+        rename = "lngE7",
+        deserialize_with = "parse_location",
+        serialize_with = "serialize_location"
+    )]
+    pub longitude: f64,
+}
+
+/// the raw points Google kept for an activity segment
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimplifiedRawPath {
+    #[serde(default)]
+    pub points: Vec<RawPathPoint>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RawPathPoint {
+    #[serde(
+        rename = "latE7",
+        deserialize_with = "parse_location",
+        serialize_with = "serialize_location"
+    )]
+    pub latitude: f64,
+    #[serde(
+        rename = "lngE7",
+        deserialize_with = "parse_location",
+        serialize_with = "serialize_location"
+    )]
+    pub longitude: f64,
+    pub accuracy_meters: Option<i32>,
+    #[serde(
+        alias = "timestampMs",
+        deserialize_with = "parse_timestamp",
+        serialize_with = "serialize_timestamp"
+    )]
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+impl TimelineObject {
+    /// start and end time of the visit or segment
+    pub fn duration(&self) -> &TimelineDuration {
+        match self {
+            TimelineObject::PlaceVisit(visit) => &visit.duration,
+            TimelineObject::ActivitySegment(segment) => &segment.duration,
+        }
+    }
 }
 
 impl Location {
@@ -306,6 +895,11 @@ impl Location {
         result
     }
 
+    /// the most likely activity at this location, if any activities were recorded
+    pub fn top_activity_type(&self) -> Option<ActivityType> {
+        self.top_activities().into_iter().next().map(|a| a.activity_type)
+    }
+
     pub fn merged_activities(&self) -> Activities {
         // merge all activities into a single list
         let mut result = Activities {
@@ -323,7 +917,7 @@ impl Location {
                 // add to all_activities, summing the confidence
                 // add the confidences to the hashmap
                 for (k, v) in activities.iter() {
-                    *all_activities.entry(*k).or_insert(0) += v;
+                    *all_activities.entry(k.clone()).or_insert(0) += v;
                 }
 
             }
@@ -333,7 +927,7 @@ impl Location {
         let mut activities : Vec<Activity> = Vec::new();
         for (act_type, confidence) in all_activities.iter() {
             activities.push(Activity {
-                activity_type: act_type.into(),
+                activity_type: act_type.clone(),
                 confidence: confidence.clone(),
             });
         }
@@ -355,7 +949,7 @@ impl Activities {
             act[0].clone()
         } else {
             Activity {
-                activity_type: "UNKNOWN".to_string(),
+                activity_type: ActivityType::UNKNOWN,
                 confidence: 0,
             }
         }
@@ -373,7 +967,7 @@ impl Activities {
         // convert to a vector, using the hashmap as a guide
         for (act_type, confidence) in activities.iter() {
             result.push(Activity {
-                activity_type: act_type.into(),
+                activity_type: act_type.clone(),
                 confidence: confidence.clone(),
             });
         }
@@ -419,7 +1013,7 @@ impl Into<HashMap<ActivityType, i32>> for &Activities {
         for key in sampled.keys() {
             let v = sampled.get(key).unwrap();
             let vsum : i32 = v.clone().into_iter().sum();
-            result.insert(*key, vsum / (v.len() as i32));
+            result.insert(key.clone(), vsum / (v.len() as i32));
         }
 
         result
@@ -432,7 +1026,7 @@ impl Into<SampledActivities> for &Activities {
         let mut result: HashMap<ActivityType, Vec<i32>> = HashMap::new();
 
         for act in self.activities.iter() {
-            let act_type: ActivityType = act.clone().into();
+            let act_type: ActivityType = act.activity_type.clone();
             let act_confidence: i32 = act.confidence.clone();
 
             // if we already have this activity type, add the confidence to it
@@ -454,13 +1048,64 @@ fn parse_timestamp<'de, D>(de: D) -> Result<DateTime<FixedOffset>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    // Expects the new Records.json timestamp format, e.g:
+    // Expects either the new Records.json timestamp format, e.g:
     //     "timestamp": "2016-08-07T04:54:00.678Z"
+    // or the legacy LocationHistory.json epoch milliseconds, e.g:
+    //     "timestampMs": "1470545640678"
     let deser_result: serde_json::Value = serde::Deserialize::deserialize(de)?;
+    timestamp_from_value(deser_result)
+}
 
+fn timestamp_from_value<E: serde::de::Error>(value: serde_json::Value) -> Result<DateTime<FixedOffset>, E> {
+    let millis = match value {
+        serde_json::Value::String(ref s) => match s.parse::<i64>() {
+            Ok(millis) => Some(millis),
+            Err(_) => {
+                return DateTime::parse_from_rfc3339(s).map_err(|e| {
+                    E::custom(format!("Invalid timestamp {:?}: {}", s, e))
+                })
+            }
+        },
+        serde_json::Value::Number(ref n) => n.as_i64(),
+        _ => None,
+    };
+
+    millis
+        .and_then(DateTime::from_timestamp_millis)
+        .map(|dt| dt.fixed_offset())
+        .ok_or_else(|| E::custom("Unexpected value"))
+}
+
+// the timestamp of a record, under `timestamp`, or `timestampMs` in older exports. a record with
+// both, such as one converted from an older export, is read by its `timestamp`.
+// for a `#[serde(flatten)]` field, as an alias can't tell the keys apart
+pub(crate) fn parse_record_timestamp<'de, D>(de: D) -> Result<DateTime<FixedOffset>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Timestamps {
+        timestamp: Option<serde_json::Value>,
+        #[serde(rename = "timestampMs")]
+        timestamp_ms: Option<serde_json::Value>,
+    }
+
+    let timestamps: Timestamps = serde::Deserialize::deserialize(de)?;
+    match timestamps.timestamp.or(timestamps.timestamp_ms) {
+        Some(value) => timestamp_from_value(value),
+        None => Err(serde::de::Error::missing_field("timestamp")),
+    }
+}
+
+fn parse_optional_timestamp<'de, D>(de: D) -> Result<Option<DateTime<FixedOffset>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // some exports write a missing timestamp as null
+    let deser_result: serde_json::Value = serde::Deserialize::deserialize(de)?;
     match deser_result {
-        serde_json::Value::String(ref s) => Ok(DateTime::parse_from_rfc3339(s).unwrap()),
-        _ => Err(serde::de::Error::custom("Unexpected value")),
+        serde_json::Value::Null => Ok(None),
+        value => timestamp_from_value(value).map(Some),
     }
 }
 
@@ -470,11 +1115,66 @@ where
 {
     let deser_result: serde_json::Value = serde::Deserialize::deserialize(de)?;
     match deser_result {
-        serde_json::Value::Number(ref i) => Ok((i.as_f64().unwrap() / 10_000_000.0) as f64),
+        serde_json::Value::Number(ref i) => i
+            .as_f64()
+            .map(|i| i / 10_000_000.0)
+            .ok_or_else(|| serde::de::Error::custom("Unexpected value")),
         _ => Err(serde::de::Error::custom("Unexpected value")),
     }
 }
 
+fn parse_optional_location<'de, D>(de: D) -> Result<Option<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    parse_location(de).map(Some)
+}
+
+// timestamps are written the way Google does, e.g. "2016-08-07T04:54:00.678Z"
+fn format_timestamp(timestamp: DateTime<FixedOffset>) -> String {
+    timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+}
+
+fn serialize_timestamp<S>(timestamp: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format_timestamp(*timestamp))
+}
+
+// the counterpart of `parse_record_timestamp`, which always writes `timestamp`
+fn serialize_record_timestamp<S>(timestamp: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeMap;
+
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry("timestamp", &format_timestamp(*timestamp))?;
+    map.end()
+}
+
+fn to_e7(degrees: f64) -> i64 {
+    (degrees * 10_000_000.0).round() as i64
+}
+
+fn serialize_location<S>(degrees: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_i64(to_e7(*degrees))
+}
+
+fn serialize_optional_location<S>(degrees: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match degrees {
+        Some(degrees) => serialize_location(degrees, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 // convert location into a Point
 impl Into<Point<f64>> for &Location {
     fn into(self) -> Point<f64> {
@@ -522,6 +1222,17 @@ impl std::fmt::Display for Location {
         };
         table.add_row(row!["altitude".bold(), altitude_str]);
 
+        // device-reported speed and heading, when available
+        if let Some(velocity) = self.velocity {
+            table.add_row(row!["velocity".bold(), format!("{} m/s", velocity)]);
+        }
+        if let Some(heading) = self.heading {
+            table.add_row(row!["heading".bold(), format!("{}°", heading)]);
+        }
+        if let Some(source) = &self.source {
+            table.add_row(row!["source".bold(), source]);
+        }
+
         // map the activities to a string
         let activity_str = match &self.activities {
             Some(activities) => {
@@ -546,15 +1257,14 @@ impl std::fmt::Display for Location {
 
 impl From<String> for ActivityType {
     fn from(value : String) -> ActivityType {
-        let a : Activity = Activity { activity_type : value, confidence : 0 };
-        a.into()
-    }
-}
-
-impl Into<ActivityType> for Activity {
-    fn into(self) -> ActivityType {
-        match self.activity_type.as_str() {
+        match value.as_str() {
             "IN_VEHICLE" => ActivityType::IN_VEHICLE,
+            "IN_ROAD_VEHICLE" => ActivityType::IN_ROAD_VEHICLE,
+            "IN_RAIL_VEHICLE" => ActivityType::IN_RAIL_VEHICLE,
+            "IN_FOUR_WHEELER_VEHICLE" => ActivityType::IN_FOUR_WHEELER_VEHICLE,
+            "IN_TWO_WHEELER_VEHICLE" => ActivityType::IN_TWO_WHEELER_VEHICLE,
+            "IN_CAR" => ActivityType::IN_CAR,
+            "IN_BUS" => ActivityType::IN_BUS,
             "EXITING_VEHICLE" => ActivityType::EXITING_VEHICLE,
             "ON_BICYCLE" => ActivityType::ON_BICYCLE,
             "ON_FOOT" => ActivityType::ON_FOOT,
@@ -563,24 +1273,26 @@ impl Into<ActivityType> for Activity {
             "TILTING" => ActivityType::TILTING,
             "UNKNOWN" => ActivityType::UNKNOWN,
             "WALKING" => ActivityType::WALKING,
-            _ => ActivityType::UNKNOWN,
+            _ => ActivityType::Other(value),
         }
     }
 }
 
+impl Into<ActivityType> for Activity {
+    fn into(self) -> ActivityType {
+        self.activity_type
+    }
+}
+
 impl Into<String> for &ActivityType {
     fn into(self) -> String {
-        match self {
-            ActivityType::IN_VEHICLE => "IN_VEHICLE".to_string(),
-            ActivityType::EXITING_VEHICLE => "EXITING_VEHICLE".to_string(),
-            ActivityType::ON_BICYCLE => "ON_BICYCLE".to_string(),
-            ActivityType::ON_FOOT => "ON_FOOT".to_string(),
-            ActivityType::RUNNING => "RUNNING".to_string(),
-            ActivityType::STILL => "STILL".to_string(),
-            ActivityType::TILTING => "TILTING".to_string(),
-            ActivityType::UNKNOWN => "UNKNOWN".to_string(),
-            ActivityType::WALKING => "WALKING".to_string(),
-        }
+        self.as_str().to_string()
+    }
+}
+
+impl std::fmt::Display for ActivityType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_str())
     }
 }
 
@@ -588,18 +1300,46 @@ impl Into<String> for &ActivityType {
 // this makes it easy to see sequences of related activities
 impl Into<ColoredString> for ActivityType {
     fn into(self) -> ColoredString {
+        let (symbol, color, background) = self.symbol();
+        let symbol = match color {
+            Some(color) => symbol.color(color),
+            None => symbol.dimmed(),
+        };
+        match background {
+            Some(background) => symbol.on_color(background),
+            None => symbol,
+        }
+    }
+}
+
+impl ActivityType {
+    // the character for this type in the calendar view, its colour, or None if it is dimmed,
+    // and its background colour
+    fn symbol(&self) -> (&'static str, Option<Color>, Option<Color>) {
         match self {
-            ActivityType::IN_VEHICLE => "$".to_string().bright_blue().on_blue(),
-            ActivityType::EXITING_VEHICLE => "^".to_string().bright_blue().on_blue(),
-            ActivityType::ON_FOOT => "#".to_string().bright_green().on_green(),
-            ActivityType::WALKING => "#".to_string().bright_green().on_green(),
-            ActivityType::RUNNING => "#".to_string().green().on_bright_green(),
-            ActivityType::ON_BICYCLE => "%".to_string().bright_yellow().on_yellow(),
-            ActivityType::STILL => ".".to_string().white(),
-            ActivityType::TILTING => "/".to_string().dimmed(),
-            ActivityType::UNKNOWN => "?".to_string().dimmed(),
+            ActivityType::IN_VEHICLE => ("$", Some(Color::BrightBlue), Some(Color::Blue)),
+            ActivityType::IN_ROAD_VEHICLE => ("$", Some(Color::BrightCyan), Some(Color::Blue)),
+            ActivityType::IN_FOUR_WHEELER_VEHICLE => ("$", Some(Color::BrightCyan), Some(Color::Blue)),
+            ActivityType::IN_CAR => ("&", Some(Color::BrightCyan), Some(Color::Blue)),
+            ActivityType::IN_BUS => ("@", Some(Color::BrightCyan), Some(Color::Blue)),
+            ActivityType::IN_TWO_WHEELER_VEHICLE => ("~", Some(Color::BrightCyan), Some(Color::Blue)),
+            ActivityType::IN_RAIL_VEHICLE => ("=", Some(Color::BrightMagenta), Some(Color::Magenta)),
+            ActivityType::EXITING_VEHICLE => ("^", Some(Color::BrightBlue), Some(Color::Blue)),
+            ActivityType::ON_FOOT => ("#", Some(Color::BrightGreen), Some(Color::Green)),
+            ActivityType::WALKING => ("#", Some(Color::BrightGreen), Some(Color::Green)),
+            ActivityType::RUNNING => ("#", Some(Color::Green), Some(Color::BrightGreen)),
+            ActivityType::ON_BICYCLE => ("%", Some(Color::BrightYellow), Some(Color::Yellow)),
+            ActivityType::STILL => (".", Some(Color::White), None),
+            ActivityType::TILTING => ("/", None, None),
+            ActivityType::UNKNOWN | ActivityType::Other(_) => ("?", None, None),
         }
     }
+
+    /// the colour of this type's symbol in the calendar view and its legend.
+    /// dimmed symbols are grey.
+    pub fn legend_color(&self) -> Color {
+        self.symbol().1.unwrap_or(Color::BrightBlack)
+    }
 }
 
 impl std::fmt::Display for Activity {
@@ -619,6 +1359,41 @@ impl std::fmt::Display for Activities {
     }
 }
 
+// locations shared by the tests of the readers and writers
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::{Activities, Activity, ActivityType, Location, Locations};
+    use chrono::DateTime;
+
+    // two locations a minute apart, the first with its accuracy, altitude, and WALKING over
+    // STILL, and the second with nothing but its position
+    pub(crate) fn locations() -> Locations {
+        let test_data = r#"{"locations": [
+            {"latitudeE7": -378224062, "longitudeE7": 1450703899, "accuracy": 12, "altitude": 52, "timestamp": "2024-01-01T09:12:00.123+10:00",
+             "activity": [{"activity": [{"type": "WALKING", "confidence": 90}, {"type": "STILL", "confidence": 10}], "timestamp": "2024-01-01T09:12:00+10:00"}]},
+            {"latitudeE7": -378224100, "longitudeE7": 1450703899, "timestamp": "2024-01-01T09:13:00+10:00"}
+        ]}"#;
+        crate::deserialize(test_data).unwrap()
+    }
+
+    // a copy of a location at another time, e.g. to start another trip or day
+    pub(crate) fn at(location: &Location, timestamp: &str) -> Location {
+        Location {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            ..location.clone()
+        }
+    }
+
+    // a location with just the one activity
+    pub(crate) fn with_activity(mut location: Location, activity_type: ActivityType) -> Location {
+        location.activities = Some(vec![Activities {
+            activities: vec![Activity { activity_type, confidence: 90 }],
+            timestamp: location.timestamp,
+        }]);
+        location
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -644,6 +1419,196 @@ mod tests {
                                 } ]
                             } ]
                             }]}"#;
-        let _locations = crate::deserialize(&test_data).filter_outliers();
+        let _locations = crate::deserialize(&test_data).unwrap().filter_outliers();
+    }
+
+    #[test]
+    fn semantic_history() {
+        let test_data = r#"{"timelineObjects" : [ {
+                            "placeVisit" : {
+                                "location" : {
+                                    "latitudeE7" : -378224062,
+                                    "longitudeE7" : 1450703899,
+                                    "placeId" : "ChIJ2fSZ3x9C1moR0HSQ8Hh8AQU",
+                                    "semanticType" : "TYPE_HOME"
+                                },
+                                "duration" : {
+                                    "startTimestamp" : "2024-01-01T09:12:00.000Z",
+                                    "endTimestamp" : "2024-01-01T10:00:00.000Z"
+                                },
+                                "placeConfidence" : "HIGH_CONFIDENCE",
+                                "visitConfidence" : 95
+                            }
+                            }, {
+                            "activitySegment" : {
+                                "startLocation" : { "latitudeE7" : -378224062, "longitudeE7" : 1450703899 },
+                                "endLocation" : { "latitudeE7" : -378100000, "longitudeE7" : 1450800000 },
+                                "duration" : {
+                                    "startTimestamp" : "2024-01-01T08:00:00.000Z",
+                                    "endTimestamp" : "2024-01-01T09:12:00.000Z"
+                                },
+                                "distance" : 2100,
+                                "activityType" : "WALKING",
+                                "activities" : [ { "activityType" : "WALKING", "probability" : 91.5 } ],
+                                "waypointPath" : { "waypoints" : [ { "latE7" : -378224062, "lngE7" : 1450703899 } ] }
+                            }
+                            } ]}"#;
+        let objects = crate::deserialize_semantic(&test_data).unwrap();
+
+        // sorted by start time, so the segment comes first
+        match &objects[0] {
+            crate::TimelineObject::ActivitySegment(segment) => {
+                assert_eq!(segment.activity_type.as_deref(), Some("WALKING"));
+                assert_eq!(segment.waypoint_path.as_ref().unwrap().waypoints.len(), 1);
+            }
+            _ => panic!("expected an activity segment"),
+        }
+        match &objects[1] {
+            crate::TimelineObject::PlaceVisit(visit) => {
+                assert_eq!(visit.location.latitude, Some(-37.8224062));
+                assert_eq!(visit.location.semantic_type.as_deref(), Some("TYPE_HOME"));
+            }
+            _ => panic!("expected a place visit"),
+        }
+    }
+
+    #[test]
+    fn legacy_timestamp_ms() {
+        let test_data = r#"{"locations" : [ {
+                            "timestampMs" : "1470545640678",
+                            "latitudeE7" : 500373489,
+                            "longitudeE7" : 83320934,
+                            "activity" : [ {
+                                "timestampMs" : "1470545640678",
+                                "activity" : [ { "type" : "STILL", "confidence" : 100 } ]
+                            } ]
+                            }, {
+                            "timestamp" : "2016-08-07T04:54:00.678Z",
+                            "latitudeE7" : 500373489,
+                            "longitudeE7" : 83320934
+                            } ]}"#;
+        let locations = crate::deserialize(&test_data).unwrap();
+
+        // both forms describe the same instant
+        assert_eq!(locations[0].timestamp, locations[1].timestamp);
+        assert_eq!(
+            locations[0].activities.as_ref().unwrap()[0].timestamp,
+            locations[1].timestamp
+        );
+
+        // a record with both is read by its timestamp, whichever comes first
+        let test_data = r#"{"locations" : [ {
+                            "timestampMs" : "1",
+                            "timestamp" : "2016-08-07T04:54:00.678Z",
+                            "latitudeE7" : 500373489,
+                            "longitudeE7" : 83320934,
+                            "activity" : [ {
+                                "timestamp" : "2016-08-07T04:54:00.678Z",
+                                "timestampMs" : "1",
+                                "activity" : [ { "type" : "STILL", "confidence" : 100 } ]
+                            } ]
+                            } ]}"#;
+        let both = crate::deserialize(&test_data).unwrap();
+        assert_eq!(both[0].timestamp, locations[1].timestamp);
+        assert_eq!(both[0].activities.as_ref().unwrap()[0].timestamp, locations[1].timestamp);
+        let lossless = crate::deserialize_lossless(&test_data).unwrap();
+        assert_eq!(lossless[0].timestamp, locations[1].timestamp);
+    }
+
+    #[test]
+    fn full_record_fields() {
+        let test_data = r#"{"locations" : [ {
+                            "latitudeE7" : -378224062,
+                            "longitudeE7" : 1450703899,
+                            "accuracy" : 12,
+                            "velocity" : 14,
+                            "heading" : 271,
+                            "altitude" : 52,
+                            "verticalAccuracy" : 3,
+                            "source" : "GPS",
+                            "deviceTag" : -1412907366,
+                            "platformType" : "ANDROID",
+                            "activeWifiScan" : { "accessPoints" : [ {
+                                "mac" : "233920876788374",
+                                "strength" : -61,
+                                "frequencyMhz" : 2437,
+                                "isConnected" : true
+                            } ] },
+                            "osLevel" : 33,
+                            "serverTimestamp" : "2024-01-01T09:12:01.123Z",
+                            "deviceTimestamp" : "2024-01-01T09:12:00.999Z",
+                            "batteryCharging" : false,
+                            "formFactor" : "PHONE",
+                            "timestamp" : "2024-01-01T09:12:00Z"
+                            } ]}"#;
+        let location = &crate::deserialize(&test_data).unwrap()[0];
+
+        assert_eq!(location.velocity, Some(14));
+        assert_eq!(location.heading, Some(271));
+        assert_eq!(location.source.as_deref(), Some("GPS"));
+        assert_eq!(location.device_tag, Some(-1412907366));
+        assert_eq!(location.battery_charging, Some(false));
+        assert!(location.server_timestamp.unwrap() > location.timestamp);
+        let scan = location.active_wifi_scan.as_ref().unwrap();
+        assert_eq!(scan.access_points[0].strength, -61);
+
+        // null is the same as leaving it out
+        let test_data = test_data.replace(r#""2024-01-01T09:12:01.123Z""#, "null");
+        let location = &crate::deserialize(&test_data).unwrap()[0];
+        assert!(location.server_timestamp.is_none());
+        assert!(location.device_timestamp.is_some());
+    }
+
+    #[test]
+    fn activity_hierarchy() {
+        use crate::{ActivityType, LocationsExt};
+
+        assert!(ActivityType::IN_CAR.is_a(&ActivityType::IN_ROAD_VEHICLE));
+        assert!(ActivityType::IN_CAR.is_a(&ActivityType::IN_VEHICLE));
+        assert!(!ActivityType::IN_RAIL_VEHICLE.is_a(&ActivityType::IN_ROAD_VEHICLE));
+        assert!(!ActivityType::IN_VEHICLE.is_a(&ActivityType::IN_CAR));
+
+        let test_data = r#"{"locations" : [
+            { "latitudeE7" : 1, "longitudeE7" : 1, "timestamp" : "2024-01-01T00:00:00Z",
+              "activity" : [ { "activity" : [ { "type" : "IN_CAR", "confidence" : 80 } ], "timestamp" : "2024-01-01T00:00:00Z" } ] },
+            { "latitudeE7" : 1, "longitudeE7" : 1, "timestamp" : "2024-01-01T00:01:00Z",
+              "activity" : [ { "activity" : [ { "type" : "IN_RAIL_VEHICLE", "confidence" : 80 } ], "timestamp" : "2024-01-01T00:01:00Z" } ] },
+            { "latitudeE7" : 1, "longitudeE7" : 1, "timestamp" : "2024-01-01T00:02:00Z",
+              "activity" : [ { "activity" : [ { "type" : "WALKING", "confidence" : 80 } ], "timestamp" : "2024-01-01T00:02:00Z" } ] }
+            ]}"#;
+        let locations = crate::deserialize(&test_data).unwrap();
+
+        assert_eq!(locations.clone().filter_by_activity("IN_VEHICLE".to_string()).len(), 2);
+        assert_eq!(locations.clone().filter_by_activity("IN_ROAD_VEHICLE".to_string()).len(), 1);
+        assert_eq!(locations.clone().filter_by_activity("ON_*".to_string()).len(), 1);
+    }
+
+    #[test]
+    fn filtered_records() {
+        use crate::{Locations, LocationsExt};
+
+        let test_data = r#"{"locations" : [
+            { "latitudeE7" : -378224062, "longitudeE7" : 1450703899, "timestamp" : "2024-01-01T00:00:00Z",
+              "activity" : [ { "activity" : [ { "type" : "WALKING", "confidence" : 80 } ], "timestamp" : "2024-01-01T00:00:00Z" } ] },
+            { "latitudeE7" : -378224100, "longitudeE7" : 1450703899, "timestamp" : "2024-01-01T00:01:00Z" },
+            { "latitudeE7" : 0, "longitudeE7" : 0, "timestamp" : "2024-01-01T00:02:00Z" },
+            { "latitudeE7" : -378224200, "longitudeE7" : 1450703899, "timestamp" : "2024-01-01T00:03:00Z" }
+            ]}"#;
+        let locations = crate::deserialize(&test_data).unwrap();
+
+        // the first location is kept once, and the one thousands of kilometers away is dropped
+        let filtered = locations.clone().filter_outliers();
+        assert_eq!(filtered.len(), 3);
+        assert_eq!(filtered[0].timestamp, locations[0].timestamp);
+        assert_ne!(filtered[1].timestamp, filtered[0].timestamp);
+        assert!(Locations::new().filter_outliers().is_empty());
+
+        // as written by `load -o`, the filtered locations read back the same
+        let mut out = Vec::new();
+        crate::serialize_to_writer(&mut out, &filtered).unwrap();
+        let read = crate::deserialize(std::str::from_utf8(&out).unwrap()).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[2].latitude, -37.82242);
+        assert_eq!(read[0].top_activity_type(), Some(crate::ActivityType::WALKING));
     }
 }
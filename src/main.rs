@@ -11,8 +11,10 @@ use colored::{ColoredString, Colorize};
 use spinner::SpinnerBuilder;
 
 use std::collections::{LinkedList, HashMap};
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
 use std::thread;
 
 extern crate rerun;
@@ -24,7 +26,7 @@ use log::{debug, error, info, log_enabled, Level};
 use textplots::{AxisBuilder, Chart, Plot, Shape};
 
 extern crate location_history;
-use location_history::{ActivityType, Location, LocationsExt, Activities, Activity};
+use location_history::{ActivityType, Location, LocationReader, LocationsExt, Activities, Activity, MonthIndex, MonthOffset, TrackSplit, GeoJsonMode, CsvColumn, CsvOptions};
 
 use clap::Parser;
 
@@ -33,6 +35,123 @@ use clap::Parser;
 #[command(bin_name = "location-history")]
 enum LocationHistoryCLI {
     Load(LoadArgs),
+    /// write a <file>.index of where each month starts, so `load -s` can skip straight to it
+    Index(IndexArgs),
+    /// write the locations out in another format, for use in other tools
+    Export(ExportArgs),
+}
+
+#[derive(clap::Args)]
+struct IndexArgs {
+    #[arg(help = "Records.json (may be .gz/.zst/.xz compressed), or the Takeout .zip/.tgz archive containing it")]
+    records_json_path: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct ExportArgs {
+    #[arg(short = 'f', long = "format", value_enum)]
+    format: ExportFormat,
+
+    #[arg(long = "split", value_enum, default_value = "day", help = "for gpx, one track per day, per trip, or per activity within a trip")]
+    split: SplitArg,
+
+    #[arg(long = "mode", value_enum, default_value = "points", help = "for geojson, a point per location, or a line per day or per activity")]
+    mode: ModeArg,
+
+    #[arg(long = "columns", value_enum, value_delimiter = ',', help = "for csv/tsv, the columns to write, in order [default: all of them]")]
+    columns: Vec<ColumnArg>,
+
+    #[arg(long = "activity-columns", help = "for csv/tsv, also write the confidence of every activity type, a column each")]
+    activity_columns: bool,
+
+    #[arg(long = "delimiter", value_parser = parse_delimiter, help = "for csv/tsv, the field delimiter, a single character or \\t [default: , or \\t]")]
+    delimiter: Option<u8>,
+
+    #[arg(short = 'o', help = "file to write to, or stdout if not given")]
+    output: Option<PathBuf>,
+
+    #[arg(help = "Records.json (may be .gz/.zst/.xz compressed), the Takeout .zip/.tgz archive containing it, a Timeline.json, or a .gpx/.kml/.csv/.tsv file")]
+    records_json_path: PathBuf,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    Gpx,
+    Kml,
+    Kmz,
+    Geojson,
+    Csv,
+    Tsv,
+    Parquet,
+    Sqlite,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SplitArg {
+    Day,
+    Trip,
+    Activity,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ModeArg {
+    Points,
+    Days,
+    Segments,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ColumnArg {
+    Timestamp,
+    Latitude,
+    Longitude,
+    Accuracy,
+    Altitude,
+    Activity,
+    Confidence,
+}
+
+impl From<ColumnArg> for CsvColumn {
+    fn from(column: ColumnArg) -> CsvColumn {
+        match column {
+            ColumnArg::Timestamp => CsvColumn::Timestamp,
+            ColumnArg::Latitude => CsvColumn::Latitude,
+            ColumnArg::Longitude => CsvColumn::Longitude,
+            ColumnArg::Accuracy => CsvColumn::Accuracy,
+            ColumnArg::Altitude => CsvColumn::Altitude,
+            ColumnArg::Activity => CsvColumn::Activity,
+            ColumnArg::Confidence => CsvColumn::Confidence,
+        }
+    }
+}
+
+// a delimiter is a single ASCII character, with "\t" or "tab" for a tab, as that's awkward to type
+fn parse_delimiter(s: &str) -> std::result::Result<u8, String> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!("expected a single character, not {:?}", s)),
+    }
+}
+
+impl From<ModeArg> for GeoJsonMode {
+    fn from(mode: ModeArg) -> GeoJsonMode {
+        match mode {
+            ModeArg::Points => GeoJsonMode::Points,
+            ModeArg::Days => GeoJsonMode::Days,
+            ModeArg::Segments => GeoJsonMode::Segments,
+        }
+    }
+}
+
+impl From<SplitArg> for TrackSplit {
+    fn from(split: SplitArg) -> TrackSplit {
+        match split {
+            SplitArg::Day => TrackSplit::Day,
+            SplitArg::Trip => TrackSplit::Trip,
+            SplitArg::Activity => TrackSplit::Activity,
+        }
+    }
 }
 
 #[derive(clap::Args)]
@@ -58,6 +177,22 @@ struct LoadArgs {
     #[arg(short = 'r', default_value = "false")]
     rerun: bool,
 
+    #[arg(short = 't', default_value = "60", help = "minutes past the end date to keep reading, for out-of-order records")]
+    tolerance: i64,
+
+    #[arg(short = 'j', help = "decode on this many threads, or 0 for one per core")]
+    threads: Option<usize>,
+
+    #[arg(long = "no-cache", help = "don't read or write the <file>.cache next to the input file")]
+    no_cache: bool,
+
+    #[arg(long = "delimiter", value_parser = parse_delimiter, help = "for a .csv/.tsv file, the field delimiter, a single character or \\t [default: , or \\t]")]
+    delimiter: Option<u8>,
+
+    #[arg(short = 'o', help = "write the filtered locations to this file as Records.json, instead of showing them")]
+    output: Option<PathBuf>,
+
+    #[arg(help = "Records.json (may be .gz/.zst/.xz compressed), the Takeout .zip/.tgz archive containing it, a Timeline.json, or a .gpx/.kml/.csv/.tsv file")]
     records_json_path: PathBuf,
 }
 
@@ -106,11 +241,136 @@ fn convert_to_xyz(loc: &Location, center_point_radius: &Option<Vec<f64>>) -> (f6
     }
 }
 
+// spawn a thread to read the json file 'in the background', and hand out the locations it sends
+fn read_in_background<F>(read: F) -> Box<dyn Iterator<Item = location_history::Result<Location>>>
+where
+    F: FnOnce(Sender<Location>) -> location_history::Result<()> + Send + 'static,
+{
+    let (tx, rx) = channel();
+    let reader_jh = thread::spawn(move || read(tx));
+
+    // once all locations are handled, report why the reader stopped early, if it did
+    let error = std::iter::once_with(move || reader_jh.join().unwrap().err()).flatten();
+    Box::new(rx.into_iter().map(Ok).chain(error.map(Err)))
+}
+
+// find where to start reading, if the file has an index
+fn locate_month(path: &Path, start: DateTime<Local>) -> Option<MonthOffset> {
+    let index = MonthIndex::read(path).unwrap_or_else(|e| {
+        error!("ignoring index: {}", e);
+        None
+    })?;
+
+    // dates are compared in the records' own timezone, which may be up to a day ahead of ours
+    index.locate((start - chrono::Duration::days(1)).fixed_offset()).cloned()
+}
+
+fn write_index(args: IndexArgs) -> Result<()> {
+    let sp = SpinnerBuilder::new("Indexing...".into()).start();
+    let index = MonthIndex::build(&args.records_json_path)?;
+    index.write(&args.records_json_path)?;
+
+    sp.message(format!("{} months indexed", index.months.len()));
+    sp.close();
+    println!();
+    Ok(())
+}
+
+// on-device exports and GPS logger files aren't laid out like Records.json, so have readers of their own
+fn read_other_format(path: &Path, delimiter: Option<u8>) -> Option<Box<dyn Iterator<Item = location_history::Result<Location>>>> {
+    let from = path.to_path_buf();
+    let is_json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    if is_json && location_history::is_timeline(path).unwrap_or(false) {
+        return Some(read_in_background(move |tx| location_history::deserialize_timeline_streaming(from, tx)));
+    }
+
+    match path.extension()?.to_ascii_lowercase().to_str()? {
+        "gpx" => Some(read_in_background(move |tx| location_history::deserialize_gpx_streaming(from, tx))),
+        "kml" => Some(read_in_background(move |tx| location_history::deserialize_kml_streaming(from, tx))),
+        "csv" => {
+            let delimiter = delimiter.unwrap_or(b',');
+            Some(read_in_background(move |tx| location_history::deserialize_csv_streaming(from, tx, delimiter)))
+        }
+        "tsv" => {
+            let delimiter = delimiter.unwrap_or(b'\t');
+            Some(read_in_background(move |tx| location_history::deserialize_csv_streaming(from, tx, delimiter)))
+        }
+        _ => None,
+    }
+}
+
+// read every location in the file, in chronological order
+fn read_locations(path: &Path) -> Result<Vec<Location>> {
+    let mut locations: Vec<Location> = match read_other_format(path, None) {
+        Some(reader) => reader.collect::<location_history::Result<_>>()?,
+        None => LocationReader::open(path)?.collect::<location_history::Result<_>>()?,
+    };
+    locations.sort_chronological();
+    Ok(locations)
+}
+
+fn export(args: ExportArgs) -> Result<()> {
+    let locations = read_locations(&args.records_json_path)?;
+
+    // a database is written in place, so needs a file
+    if let ExportFormat::Sqlite = args.format {
+        let Some(path) = &args.output else {
+            anyhow::bail!("sqlite needs a file to write to, given with -o");
+        };
+        location_history::write_sqlite(path, &locations)?;
+        return Ok(());
+    }
+
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    match args.format {
+        ExportFormat::Gpx => location_history::write_gpx(&mut writer, &locations, args.split.into())?,
+        ExportFormat::Kml => location_history::write_kml(&mut writer, &locations)?,
+        ExportFormat::Geojson => location_history::write_geojson(&mut writer, &locations, args.mode.into())?,
+        ExportFormat::Csv | ExportFormat::Tsv => {
+            let default = if matches!(args.format, ExportFormat::Tsv) { b'\t' } else { b',' };
+            let options = CsvOptions {
+                delimiter: args.delimiter.unwrap_or(default),
+                columns: if args.columns.is_empty() {
+                    CsvColumn::ALL.to_vec()
+                } else {
+                    args.columns.iter().map(|&c| c.into()).collect()
+                },
+                activity_columns: args.activity_columns,
+            };
+            location_history::write_csv(&mut writer, &locations, &options)?
+        }
+        ExportFormat::Kmz => {
+            // zip archives are written out of order, so can't go straight to stdout
+            let mut kmz = Cursor::new(Vec::new());
+            location_history::write_kmz(&mut kmz, &locations)?;
+            writer.write_all(kmz.get_ref())?;
+            writer.flush()?;
+        }
+        ExportFormat::Parquet => {
+            // the parquet writer has to be Send, which stdout isn't, and the compressed output is small
+            let mut parquet = Vec::new();
+            location_history::write_parquet(&mut parquet, &locations)?;
+            writer.write_all(&parquet)?;
+            writer.flush()?;
+        }
+        ExportFormat::Sqlite => unreachable!(),
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     // env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     env_logger::init();
 
-    let LocationHistoryCLI::Load(args) = LocationHistoryCLI::parse();
+    let args = match LocationHistoryCLI::parse() {
+        LocationHistoryCLI::Load(args) => args,
+        LocationHistoryCLI::Index(args) => return write_index(args),
+        LocationHistoryCLI::Export(args) => return export(args),
+    };
 
     // parse start_date and end_date, if provided. assume the format is yy_mm_dd, and is provided in our local timezone
     let start_date: Option<DateTime<Local>> = args.start_date.map(|s| {
@@ -127,23 +387,68 @@ fn main() -> Result<()> {
             .unwrap()
     });
 
-    // a background thread performs streaming deserialization, while the main thread
-    // handles the Locations as they are deserialized. filtering is performed in the main thread.
-    let (tx, rx) = channel();
+    // locations are filtered as they are deserialized, so only those in range are kept in memory
     let mut locations: Vec<Location> = Vec::new();
     let mut locations_count: u64 = 0;
 
-    // spawn a thread to read the json file 'in the background'
-    let reader_jh = thread::spawn(move || {
-        location_history::deserialize_streaming(args.records_json_path, tx);
-    });
+    // a cache from an earlier run skips parsing entirely
+    let cached = if args.no_cache {
+        None
+    } else {
+        location_history::read_cache(&args.records_json_path).unwrap_or_else(|e| {
+            error!("ignoring cache: {}", e);
+            None
+        })
+    };
+    // everything read from the source is cached, not just what passes the filters. the date range
+    // is the only filter applied while reading, so without one, `locations` is everything, and is
+    // cached as it is rather than kept twice
+    let mut cache_all = !args.no_cache && cached.is_none();
+    let mut to_cache: Option<Vec<Location>> = (cache_all && (start_date.is_some() || end_date.is_some())).then(Vec::new);
+
+    let path = args.records_json_path.clone();
+    let reader: Box<dyn Iterator<Item = location_history::Result<Location>>> =
+        if let Some(cached) = cached {
+            Box::new(cached.into_iter().map(Ok))
+        } else if let Some(reader) = read_other_format(&path, args.delimiter) {
+            // these aren't in chronological order, e.g. a Timeline.json has its paths before its
+            // raw signals, and GPX tracks may overlap, so they're read in full and sorted first
+            let mut other: Vec<Location> = reader.collect::<location_history::Result<_>>()?;
+            other.sort_chronological();
+            Box::new(other.into_iter().map(Ok))
+        } else if let Some(threads) = args.threads {
+            read_in_background(move |tx| location_history::deserialize_streaming_parallel(path, tx, threads))
+        } else if start_date.is_some() || end_date.is_some() {
+            // only part of the file is read
+            cache_all = false;
+            to_cache = None;
+            let reader = match start_date.and_then(|start| locate_month(&path, start)) {
+                Some(month) => LocationReader::open_at(&path, &month)?,
+                None => LocationReader::open(&path)?,
+            };
+
+            // dates are compared in the records' own timezone below, which may be up to a day
+            // either side of ours, so the reader only has to narrow it down
+            let day = chrono::Duration::days(1);
+            Box::new(reader.time_window(
+                start_date.map(|start| (start - day).fixed_offset()),
+                end_date.map(|end| (end + day).fixed_offset()),
+                chrono::Duration::minutes(args.tolerance),
+            ))
+        } else {
+            Box::new(LocationReader::open(&path)?)
+        };
 
-    // main thread handles the Locations as they are deserialized
     let sp = SpinnerBuilder::new("Loading data...".into()).start();
 
-    for loc in rx {
+    for loc in reader {
+        let loc = loc?;
         locations_count += 1;
 
+        if let Some(to_cache) = to_cache.as_mut() {
+            to_cache.push(loc.clone());
+        }
+
         sp.update(format!(
             "{} loaded, {} parsed",
             locations.len(),
@@ -168,11 +473,21 @@ fn main() -> Result<()> {
         // if the record limit is reached, stop
         if let Some(record_limit) = args.record_limit {
             if locations.len() >= record_limit {
+                // the cache would be incomplete
+                cache_all = false;
+                to_cache = None;
                 break;
             }
         }
     }
 
+    if cache_all {
+        let to_cache = to_cache.as_ref().unwrap_or(&locations);
+        if let Err(e) = location_history::write_cache(&args.records_json_path, to_cache) {
+            error!("failed to write cache: {}", e);
+        }
+    }
+
     sp.message(format!(
         "{} returned, {} parsed",
         locations.len(),
@@ -217,6 +532,14 @@ fn main() -> Result<()> {
         );
     }
 
+    // e.g. to share a subset of the history with tools that expect a Takeout
+    if let Some(output) = &args.output {
+        let mut writer = BufWriter::new(File::create(output)?);
+        location_history::serialize_to_writer(&mut writer, &filtered_locations)?;
+        writer.flush()?;
+        println!("{} locations written to {}", filtered_locations.len(), output.display());
+        return Ok(());
+    }
 
     // group the entries by month 
     let grouped: Vec<Vec<Location>> = filtered_locations
@@ -399,8 +722,5 @@ fn main() -> Result<()> {
     //    }
     //}
 
-    // wait for the reader to finish
-    reader_jh.join().unwrap();
-
     Ok(())
 }
@@ -0,0 +1,285 @@
+//! Multi-threaded reading of large `Records.json` files.
+//!
+//! Decoding is far slower than reading the bytes, so a single thread scans the file for the
+//! boundaries between records in the `locations` array, without decoding them. The records are
+//! handed out in chunks to a pool of workers, and the decoded chunks are put back into file order
+//! before they are sent on.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{open_records, Error, Location, Locations, Result};
+
+// roughly how many bytes of records go into each chunk
+const CHUNK_SIZE: usize = 1 << 20;
+
+// a run of consecutive records from the locations array
+struct Chunk {
+    data: Vec<u8>,
+    // each record's bytes within `data`, and its byte offset within the file
+    records: Vec<(Range<usize>, u64)>,
+    // index of the first record within the locations array
+    first_index: usize,
+}
+
+impl Chunk {
+    fn new(first_index: usize) -> Chunk {
+        Chunk {
+            data: Vec::with_capacity(CHUNK_SIZE + CHUNK_SIZE / 8),
+            records: Vec::new(),
+            first_index,
+        }
+    }
+
+    // decodes the records up to the first bad one, if there is one
+    fn decode(&self) -> (Locations, Result<()>) {
+        let mut locations = Vec::with_capacity(self.records.len());
+
+        for (i, (range, offset)) in self.records.iter().enumerate() {
+            match serde_json::from_slice(&self.data[range.clone()]) {
+                Ok(location) => locations.push(location),
+                Err(e) => {
+                    let error = Error::Record {
+                        index: self.first_index + i,
+                        offset: Some(*offset),
+                        source: e.into(),
+                    };
+                    return (locations, Err(error));
+                }
+            }
+        }
+
+        (locations, Ok(()))
+    }
+}
+
+type Decoded = (usize, (Locations, Result<()>));
+
+/// Same as `deserialize_streaming`, but the records are decoded on `threads` worker threads.
+/// Locations are still sent in file order. If `threads` is 0, one worker per core is used.
+///
+/// Only the `locations` array is read, so this is meant for `Records.json`, not the
+/// other exports.
+pub fn deserialize_streaming_parallel(from: PathBuf, tx: Sender<Location>, threads: usize) -> Result<()> {
+    let reader = open_records(&from)?;
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    thread::scope(|scope| {
+        // bounded, so the scanner doesn't get too far ahead of the workers
+        let (chunk_tx, chunk_rx) = sync_channel::<(usize, Chunk)>(threads * 2);
+        let (result_tx, result_rx) = channel::<Decoded>();
+
+        let scanner = scope.spawn(move || scan_records(reader, chunk_tx));
+
+        let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+        for _ in 0..threads {
+            let chunk_rx = Arc::clone(&chunk_rx);
+            let result_tx = result_tx.clone();
+            scope.spawn(move || worker(chunk_rx, result_tx));
+        }
+        drop(result_tx);
+
+        // the workers finish out of order, so hold on to chunks until those before them are sent
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (n, result) in result_rx {
+            pending.insert(n, result);
+
+            while let Some((locations, result)) = pending.remove(&next) {
+                for location in locations {
+                    if tx.send(location).is_err() {
+                        return Ok(());
+                    }
+                }
+                result?;
+                next += 1;
+            }
+        }
+
+        scanner.join().unwrap()
+    })
+}
+
+fn worker(chunk_rx: Arc<Mutex<Receiver<(usize, Chunk)>>>, result_tx: Sender<Decoded>) {
+    loop {
+        // only hold the lock while waiting, not while decoding
+        let next = chunk_rx.lock().unwrap().recv();
+        let Ok((n, chunk)) = next else {
+            return;
+        };
+
+        if result_tx.send((n, chunk.decode())).is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum ScanState {
+    // looking for the locations array in the top-level object
+    Seeking,
+    // inside the locations array
+    Records,
+}
+
+// Splits the locations array into chunks of records, by keeping track of strings and nesting.
+fn scan_records<R: Read>(reader: R, chunk_tx: SyncSender<(usize, Chunk)>) -> Result<()> {
+    let mut reader = BufReader::with_capacity(1 << 16, reader);
+
+    let mut state = ScanState::Seeking;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    // while seeking: the last string at the top level, and the key it turned out to be
+    let mut string = Vec::new();
+    let mut key: Option<Vec<u8>> = None;
+
+    let mut chunk = Chunk::new(0);
+    let mut chunk_count = 0;
+    // where the current record starts, within `chunk.data` and within the file
+    let mut record_start: Option<(usize, u64)> = None;
+    let mut position: u64 = 0;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            // still hand out the records before the end, as the single-threaded reader would
+            if !chunk.records.is_empty() {
+                let _ = chunk_tx.send((chunk_count, chunk));
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no complete locations array found").into());
+        }
+        let len = buf.len();
+        // start of the bytes in `buf` which belong to the current record
+        let mut run_start = 0;
+
+        for (i, &b) in buf.iter().enumerate() {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' {
+                    escaped = true;
+                } else if b == b'"' {
+                    in_string = false;
+                    continue;
+                }
+                // escape sequences are kept as-is, which is fine for comparing keys
+                if state == ScanState::Seeking && depth == 1 {
+                    string.push(b);
+                }
+                continue;
+            }
+
+            match state {
+                ScanState::Seeking => match b {
+                    b'"' => {
+                        in_string = true;
+                        string.clear();
+                    }
+                    b':' if depth == 1 => key = Some(std::mem::take(&mut string)),
+                    b'[' if depth == 1 && key.as_deref() == Some(b"locations") => {
+                        state = ScanState::Records;
+                        depth = 0;
+                        run_start = i + 1;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    b',' if depth == 1 => key = None,
+                    _ => (),
+                },
+                ScanState::Records => {
+                    // depth is relative to the locations array
+                    if depth == 0 && (b == b',' || b == b']') {
+                        if let Some((start, offset)) = record_start.take() {
+                            chunk.data.extend_from_slice(&buf[run_start..i]);
+                            chunk.records.push((start..chunk.data.len(), offset));
+
+                            if chunk.data.len() >= CHUNK_SIZE || b == b']' {
+                                let next = Chunk::new(chunk.first_index + chunk.records.len());
+                                if chunk_tx.send((chunk_count, std::mem::replace(&mut chunk, next))).is_err() {
+                                    // the workers have stopped, so nobody is waiting for the rest
+                                    return Ok(());
+                                }
+                                chunk_count += 1;
+                            }
+                        }
+                        if b == b']' {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+
+                    if record_start.is_none() {
+                        if b.is_ascii_whitespace() {
+                            continue;
+                        }
+                        record_start = Some((chunk.data.len(), position + i as u64));
+                        run_start = i;
+                    }
+
+                    match b {
+                        b'"' => in_string = true,
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        if record_start.is_some() {
+            chunk.data.extend_from_slice(&buf[run_start..]);
+        }
+        reader.consume(len);
+        position += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parallel_order() {
+        let records: Vec<String> = (0..20_000)
+            .map(|i| {
+                format!(
+                    r#"{{"latitudeE7": {}, "longitudeE7": 1, "activity": [{{"activity": [{{"type": "STILL", "confidence": 100}}], "timestamp": "2024-01-01T00:00:00Z"}}], "source": "a \"quoted\" ] }} string", "timestamp": "2024-01-01T00:00:00Z"}}"#,
+                    i
+                )
+            })
+            .collect();
+        let test_data = format!(r#"{{"other": {{"locations": [1]}}, "locations" : [ {} ] }}"#, records.join(",\n"));
+
+        let path = std::env::temp_dir().join(format!("location_history_parallel_{}.json", std::process::id()));
+        std::fs::write(&path, &test_data).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        crate::deserialize_streaming_parallel(path.clone(), tx, 4).unwrap();
+        let latitudes: Vec<i64> = rx.iter().map(|l| (l.latitude * 10_000_000.0).round() as i64).collect();
+        assert_eq!(latitudes, (0..20_000).collect::<Vec<i64>>());
+
+        // a bad record is reported at the same position as the single-threaded reader does
+        std::fs::write(&path, test_data.replacen("\"latitudeE7\": 12345,", "\"latitudeE7\": false,", 1)).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let error = crate::deserialize_streaming_parallel(path.clone(), tx, 4).unwrap_err();
+        assert_eq!(rx.iter().count(), 12345);
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let expected = crate::deserialize_streaming(path.clone(), tx).unwrap_err();
+        match (error, expected) {
+            (crate::Error::Record { index, offset, .. }, crate::Error::Record { index: i, offset: o, .. }) => {
+                assert_eq!((index, offset), (i, o));
+            }
+            other => panic!("expected record errors, got {:?}", other),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
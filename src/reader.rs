@@ -0,0 +1,318 @@
+//! Pull-based reading of `Records.json`.
+//!
+//! `LocationReader` decodes one `Location` at a time from any `Read`, so callers can chain
+//! `.filter()`, `.take()` or `.take_while()` without spawning a thread or managing a channel.
+//! Reading stops as soon as the iterator is dropped.
+
+use std::io::Read;
+use std::path::Path;
+use struson::json_path;
+use chrono::{DateTime, Duration, FixedOffset};
+use struson::reader::{JsonReader, JsonStreamReader, ReaderError, TransferError};
+use struson::writer::{JsonStreamWriter, JsonWriter};
+
+use crate::index::RecordTimestamp;
+use crate::input::open_records_at;
+use crate::{next_record, open_records, Error, Location, LosslessLocation, MonthOffset, Result, SkipReport};
+
+/// Iterator over the locations in a `Records.json`, decoded as they are read.
+///
+/// By default, iteration ends after the first record which can't be decoded, which is
+/// returned as an `Err`. See `lenient` and `lossless` for the other options.
+pub struct LocationReader<R: Read> {
+    json_reader: JsonStreamReader<R>,
+    lenient: bool,
+    lossless: bool,
+    started: bool,
+    done: bool,
+    index: usize,
+    // where the stream starts within the file, when it doesn't start at the beginning
+    offset_base: u64,
+    window: Option<TimeWindow>,
+    // the record being read, when it is copied before decoding
+    buffer: Vec<u8>,
+    report: SkipReport,
+}
+
+// records from `start` until `end`, and how far past `end` to keep looking for stragglers
+struct TimeWindow {
+    start: Option<DateTime<FixedOffset>>,
+    end: Option<DateTime<FixedOffset>>,
+    tolerance: Duration,
+}
+
+impl TimeWindow {
+    fn contains(&self, timestamp: DateTime<FixedOffset>) -> bool {
+        self.start.is_none_or(|start| timestamp >= start) && self.end.is_none_or(|end| timestamp < end)
+    }
+}
+
+impl LocationReader<Box<dyn Read + Send>> {
+    /// Opens a `Records.json`, or a compressed file or Takeout archive containing it,
+    /// in the same manner as `open_records`.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(LocationReader::new(open_records(path)?))
+    }
+
+    /// Opens a `Records.json` in the same manner as `open`, but starts reading at the record
+    /// `month` points to, e.g. one found with `MonthIndex::locate`.
+    pub fn open_at(path: &Path, month: &MonthOffset) -> Result<Self> {
+        // make the remainder of the array look like a Records.json of its own
+        let head: &[u8] = b"{\"locations\":[";
+        let rest = open_records_at(path, month.offset)?;
+
+        let mut reader = LocationReader::new(Box::new(head.chain(rest)) as Box<dyn Read + Send>);
+        reader.index = month.index;
+        reader.offset_base = month.offset - head.len() as u64;
+        Ok(reader)
+    }
+}
+
+impl<R: Read> LocationReader<R> {
+    /// Reads locations from the `locations` array of a `Records.json`.
+    pub fn new(reader: R) -> Self {
+        LocationReader {
+            json_reader: JsonStreamReader::new(reader),
+            lenient: false,
+            lossless: false,
+            started: false,
+            done: false,
+            index: 0,
+            offset_base: 0,
+            window: None,
+            buffer: Vec::new(),
+            report: SkipReport::default(),
+        }
+    }
+
+    /// Skip over records which can't be decoded, rather than stopping at them.
+    /// The skipped records are listed in `skip_report`.
+    ///
+    /// If the JSON itself is broken, e.g. the file is truncated, there is no way to find
+    /// the start of the next record, so iteration still ends there.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Keep the fields `Location` doesn't model in `Location::extra`, as `deserialize_lossless` does.
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// Only hand out the locations from `start` (inclusive) until `end` (exclusive).
+    ///
+    /// Records outside the window are only decoded as far as their timestamp. As Records.json
+    /// is in chronological order, iteration ends at the first record which is `tolerance` or
+    /// more past `end`. Records a little out of order, within the tolerance, are still found.
+    ///
+    /// This doesn't skip the records before `start` without reading them, for that see `open_at`.
+    pub fn time_window(
+        mut self,
+        start: Option<DateTime<FixedOffset>>,
+        end: Option<DateTime<FixedOffset>>,
+        tolerance: Duration,
+    ) -> Self {
+        self.window = Some(TimeWindow { start, end, tolerance });
+        self
+    }
+
+    /// the records which were skipped so far, when reading leniently
+    pub fn skip_report(&self) -> &SkipReport {
+        &self.report
+    }
+
+    /// the records which were skipped, when reading leniently
+    pub fn into_skip_report(self) -> SkipReport {
+        self.report
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.json_reader.seek_to(&json_path!["locations"])?;
+        self.json_reader.begin_array()?;
+        Ok(())
+    }
+
+    fn read_next(&mut self) -> Result<Option<Location>> {
+        if !self.json_reader.has_next()? {
+            return Ok(None);
+        }
+
+        let location = if self.lossless {
+            next_record::<_, LosslessLocation>(&mut self.json_reader, self.index).map(Location::from)
+        } else {
+            next_record(&mut self.json_reader, self.index)
+        };
+        self.index += 1;
+
+        match location {
+            Ok(location) => Ok(Some(location)),
+            Err(Error::Record { index, offset, source }) => Err(Error::Record {
+                index,
+                offset: offset.map(|o| o + self.offset_base),
+                source,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Copies each record into a buffer before decoding it, so a record which turns out not to
+    // be a valid location can be stepped over, and records outside the time window are only
+    // decoded as far as their timestamp.
+    fn read_next_buffered(&mut self) -> Result<Option<Location>> {
+        loop {
+            if !self.json_reader.has_next()? {
+                return Ok(None);
+            }
+
+            let index = self.index;
+            let offset = self.json_reader.current_position(false).data_pos.map(|o| o + self.offset_base);
+            self.index += 1;
+
+            self.buffer.clear();
+            let mut writer = JsonStreamWriter::new(&mut self.buffer);
+            let copied = self.json_reader.transfer_to(&mut writer);
+            match copied {
+                Ok(()) => {
+                    writer.finish_document()?;
+                }
+                Err(TransferError::WriterError(e)) => return Err(e.into()),
+                // the rest of the file can't be read either
+                Err(TransferError::ReaderError(e @ ReaderError::IoError { .. })) => return Err(e.into()),
+                // there is no way to find the start of the next record. as in `read_next`, this is
+                // an `Error::Json` rather than a problem with the record
+                Err(TransferError::ReaderError(e)) => {
+                    if self.lenient {
+                        self.report.skip(index, offset, &e);
+                        return Ok(None);
+                    }
+                    return Err(e.into());
+                }
+            }
+
+            if let Some(window) = &self.window {
+                // a record without a valid timestamp is reported below, when it is decoded
+                if let Ok(record) = serde_json::from_slice::<RecordTimestamp>(&self.buffer) {
+                    if window.end.is_some_and(|end| record.timestamp >= end + window.tolerance) {
+                        return Ok(None);
+                    }
+                    if !window.contains(record.timestamp) {
+                        continue;
+                    }
+                }
+            }
+
+            let location = if self.lossless {
+                serde_json::from_slice::<LosslessLocation>(&self.buffer).map(Location::from)
+            } else {
+                serde_json::from_slice::<Location>(&self.buffer)
+            };
+
+            match location {
+                Ok(location) => return Ok(Some(location)),
+                Err(e) if self.lenient => self.report.skip(index, offset, e),
+                Err(e) => return Err(Error::Record { index, offset, source: e.into() }),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for LocationReader<R> {
+    type Item = Result<Location>;
+
+    fn next(&mut self) -> Option<Result<Location>> {
+        if self.done {
+            return None;
+        }
+
+        let begun = if self.started {
+            Ok(())
+        } else {
+            self.started = true;
+            self.begin()
+        };
+
+        let next = begun.and_then(|_| {
+            if self.lenient || self.window.is_some() {
+                self.read_next_buffered()
+            } else {
+                self.read_next()
+            }
+        });
+
+        match next {
+            Ok(Some(location)) => Some(Ok(location)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LocationReader;
+    use chrono::Duration;
+
+    #[test]
+    fn iterator_adapters() {
+        let test_data = r#"{"locations": [
+            {"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T00:00:00Z"},
+            {"latitudeE7": 3, "longitudeE7": 4, "timestamp": "2024-01-01T00:01:00Z", "deviceTag": 7},
+            {"latitudeE7": 5, "longitudeE7": 6, "timestamp": "2024-01-01T00:02:00Z"},
+            {"latitudeE7": "broken"}
+        ]}"#;
+
+        // the broken record is never reached
+        let latitudes: Vec<f64> = LocationReader::new(test_data.as_bytes())
+            .map(Result::unwrap)
+            .filter(|l| l.device_tag.is_none())
+            .take(2)
+            .map(|l| l.latitude)
+            .collect();
+        assert_eq!(latitudes, [0.0000001, 0.0000005]);
+
+        let mut reader = LocationReader::new(test_data.as_bytes());
+        assert_eq!(reader.by_ref().filter(Result::is_ok).count(), 3);
+        assert!(reader.next().is_none());
+
+        let mut reader = LocationReader::new(test_data.as_bytes()).lenient(true);
+        assert_eq!(reader.by_ref().count(), 3);
+        assert_eq!(reader.skip_report().count(), 1);
+    }
+
+    #[test]
+    fn time_window() {
+        let timestamps = ["00:00", "00:10", "00:20", "00:15", "00:30", "00:25", "00:40"];
+        let records: Vec<String> = timestamps
+            .iter()
+            .map(|t| format!(r#"{{"latitudeE7": 1, "longitudeE7": 2, "timestamp": "2024-01-01T{}:00Z"}}"#, t))
+            .collect();
+        // records past the tolerance are never decoded
+        let test_data = format!(r#"{{"locations": [{}, {{"timestamp": "2024-01-01T00:50:00Z"}}]}}"#, records.join(", "));
+
+        let at = |t: &str| Some(chrono::DateTime::parse_from_rfc3339(&format!("2024-01-01T{}:00Z", t)).unwrap());
+        let minutes = |locations: Vec<crate::Location>| -> Vec<String> {
+            locations.iter().map(|l| l.timestamp.format("%M").to_string()).collect()
+        };
+
+        let strict = LocationReader::new(test_data.as_bytes()).time_window(at("00:10"), at("00:20"), Duration::zero());
+        assert_eq!(minutes(strict.collect::<crate::Result<_>>().unwrap()), ["10"]);
+
+        let tolerant = LocationReader::new(test_data.as_bytes()).time_window(at("00:10"), at("00:20"), Duration::minutes(10));
+        assert_eq!(minutes(tolerant.collect::<crate::Result<_>>().unwrap()), ["10", "15"]);
+
+        // broken JSON is the same error either way, whether or not records are buffered for the window
+        let broken = test_data.replacen("\"timestamp\"", "\"timestamp\",", 1);
+        let unbuffered = LocationReader::new(broken.as_bytes()).collect::<crate::Result<Vec<_>>>();
+        assert!(matches!(unbuffered, Err(crate::Error::Json(_))));
+        let buffered = LocationReader::new(broken.as_bytes()).time_window(at("00:10"), None, Duration::zero());
+        assert!(matches!(buffered.collect::<crate::Result<Vec<_>>>(), Err(crate::Error::Json(_))));
+    }
+}
@@ -0,0 +1,115 @@
+//! Splitting locations into tracks, for the exporters.
+//!
+//! Locations are expected to be in chronological order, e.g. after `sort_chronological`.
+
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::{ActivityType, Location};
+
+/// a gap between two locations longer than this many seconds starts a new trip
+pub const TRIP_GAP_SECONDS: i64 = 600;
+
+/// how to split locations into tracks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackSplit {
+    /// one track per calendar day, in the timezone each location was recorded in
+    Day,
+    /// one track per trip, i.e. a run of locations with no gap longer than `TRIP_GAP_SECONDS`
+    Trip,
+    /// one track per run of locations with the same top activity, also split between trips.
+    /// Locations without any activities belong to the track before them.
+    Activity,
+}
+
+/// the day a location was recorded on, in the timezone it was recorded in
+pub fn local_date(location: &Location) -> NaiveDate {
+    location.timestamp.date_naive()
+}
+
+/// Splits chronologically ordered locations into tracks.
+pub fn split_tracks(locations: &[Location], split: TrackSplit) -> Vec<&[Location]> {
+    match split {
+        TrackSplit::Day => locations.chunk_by(|a, b| local_date(a) == local_date(b)).collect(),
+        TrackSplit::Trip => locations
+            .chunk_by(|a, b| b.timestamp.timestamp() - a.timestamp.timestamp() <= TRIP_GAP_SECONDS)
+            .collect(),
+        TrackSplit::Activity => split_by_activity(locations),
+    }
+}
+
+fn split_by_activity(locations: &[Location]) -> Vec<&[Location]> {
+    let mut tracks = Vec::new();
+    let mut start = 0;
+    // the activity of the current track, from the first location in it which has one
+    let mut activity: Option<ActivityType> = None;
+
+    for (i, location) in locations.iter().enumerate() {
+        let top = location.top_activity_type();
+        let gap = i > 0 && location.timestamp.timestamp() - locations[i - 1].timestamp.timestamp() > TRIP_GAP_SECONDS;
+        let changed = matches!((&activity, &top), (Some(a), Some(b)) if a != b);
+
+        if gap || changed {
+            tracks.push(&locations[start..i]);
+            start = i;
+            activity = None;
+        }
+        if activity.is_none() {
+            activity = top;
+        }
+    }
+
+    if start < locations.len() {
+        tracks.push(&locations[start..]);
+    }
+    tracks
+}
+
+// the top activity of the most locations in the track
+pub(crate) fn most_common_activity(track: &[Location]) -> Option<ActivityType> {
+    let mut counts: HashMap<ActivityType, usize> = HashMap::new();
+    for activity_type in track.iter().filter_map(Location::top_activity_type) {
+        *counts.entry(activity_type).or_insert(0) += 1;
+    }
+    // ties go to the name which sorts first, so the output doesn't change from run to run
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.as_str().cmp(a.0.as_str())))
+        .map(|(activity_type, _)| activity_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{split_tracks, TrackSplit};
+
+    #[test]
+    fn track_split() {
+        let times = ["2024-01-01T09:00:00+10:00", "2024-01-01T09:05:00+10:00", "2024-01-01T18:00:00+10:00", "2024-01-02T08:00:00+10:00"];
+        let mut locations: Vec<crate::Location> = times
+            .iter()
+            .map(|t| crate::Location {
+                timestamp: chrono::DateTime::parse_from_rfc3339(t).unwrap(),
+                ..Default::default()
+            })
+            .collect();
+
+        let lengths = |locations: &[crate::Location], split| split_tracks(locations, split).iter().map(|t| t.len()).collect::<Vec<_>>();
+        // in UTC, the last location would be on the first day too
+        assert_eq!(lengths(&locations, TrackSplit::Day), [3, 1]);
+        assert_eq!(lengths(&locations, TrackSplit::Trip), [2, 1, 1]);
+        assert!(split_tracks(&[], TrackSplit::Day).is_empty());
+
+        // the second location has no activities, so stays with the first
+        let activity = |activity_type: &str| {
+            Some(vec![crate::Activities {
+                activities: vec![crate::Activity { activity_type: activity_type.to_string().into(), confidence: 100 }],
+                timestamp: chrono::DateTime::parse_from_rfc3339(times[0]).unwrap(),
+            }])
+        };
+        locations[0].activities = activity("WALKING");
+        locations[1].activities = None;
+        locations[2].activities = activity("IN_CAR");
+        locations[3].activities = activity("IN_CAR");
+        assert_eq!(lengths(&locations, TrackSplit::Activity), [2, 1, 1]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Writing locations to a SQLite database, for ad-hoc SQL queries.
+//!
+//! There are three tables:
+//!
+//! * `locations`, one row per location. `timestamp` is milliseconds since the epoch, with the
+//!   offset of the timezone it was recorded in, in seconds, in `utc_offset`. `lat_cell` and
+//!   `lon_cell` place the location on a grid of `GRID_CELLS_PER_DEGREE` cells per degree, so
+//!   that an area can be found with the index, e.g.
+//!   `WHERE lat_cell BETWEEN -3783 AND -3781 AND lon_cell BETWEEN 14506 AND 14508`.
+//! * `activities`, one row per activity of each sample, with the `location_id` it belongs to.
+//! * `trips`, one row per trip (see `TrackSplit::Trip`), with its first and last location, and
+//!   its most common top activity.
+//!
+//! Both `timestamp` and the grid cells are indexed.
+//!
+//! Stays aren't written, as they come from the Semantic Location History (see `PlaceVisit`),
+//! rather than from locations.
+
+use rusqlite::{params, Connection};
+use std::io;
+use std::path::Path;
+
+use crate::segments::most_common_activity;
+use crate::{split_tracks, Location, Result, TrackSplit};
+
+/// the size of the grid cells in the `locations` table, about 1.1km north to south
+pub const GRID_CELLS_PER_DEGREE: f64 = 100.0;
+
+const SCHEMA: &str = "
+CREATE TABLE locations (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    utc_offset INTEGER NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    lat_cell INTEGER NOT NULL,
+    lon_cell INTEGER NOT NULL,
+    accuracy INTEGER,
+    altitude INTEGER,
+    velocity INTEGER,
+    heading INTEGER,
+    vertical_accuracy INTEGER,
+    source TEXT
+);
+CREATE TABLE activities (
+    location_id INTEGER NOT NULL REFERENCES locations(id),
+    timestamp INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    confidence INTEGER NOT NULL
+);
+CREATE TABLE trips (
+    id INTEGER PRIMARY KEY,
+    start_timestamp INTEGER NOT NULL,
+    end_timestamp INTEGER NOT NULL,
+    first_location_id INTEGER NOT NULL REFERENCES locations(id),
+    last_location_id INTEGER NOT NULL REFERENCES locations(id),
+    activity TEXT,
+    points INTEGER NOT NULL
+);
+";
+
+// created once the rows are in, which is quicker than keeping them up to date while inserting
+const INDEXES: &str = "
+CREATE INDEX locations_timestamp ON locations(timestamp);
+CREATE INDEX locations_cell ON locations(lat_cell, lon_cell);
+CREATE INDEX activities_location ON activities(location_id);
+CREATE INDEX trips_start ON trips(start_timestamp);
+";
+
+fn grid_cell(degrees: f64) -> i64 {
+    (degrees * GRID_CELLS_PER_DEGREE).floor() as i64
+}
+
+/// Writes locations to a new SQLite database at `path`, replacing any file already there.
+/// The locations are expected to be in chronological order.
+pub fn write_sqlite(path: &Path, locations: &[Location]) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => (),
+    }
+    write_tables(path, locations).map_err(|e| io::Error::other(e).into())
+}
+
+fn write_tables(path: &Path, locations: &[Location]) -> rusqlite::Result<()> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    {
+        let mut insert_location = transaction.prepare(
+            "INSERT INTO locations (id, timestamp, utc_offset, latitude, longitude, lat_cell, lon_cell,
+                accuracy, altitude, velocity, heading, vertical_accuracy, source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        )?;
+        let mut insert_activity = transaction
+            .prepare("INSERT INTO activities (location_id, timestamp, type, confidence) VALUES (?1, ?2, ?3, ?4)")?;
+
+        for (id, location) in locations.iter().enumerate() {
+            insert_location.execute(params![
+                id as i64,
+                location.timestamp.timestamp_millis(),
+                location.timestamp.offset().local_minus_utc(),
+                location.latitude,
+                location.longitude,
+                grid_cell(location.latitude),
+                grid_cell(location.longitude),
+                location.accuracy,
+                location.altitude,
+                location.velocity,
+                location.heading,
+                location.vertical_accuracy,
+                location.source,
+            ])?;
+
+            for sample in location.activities.iter().flatten() {
+                for activity in &sample.activities {
+                    insert_activity.execute(params![
+                        id as i64,
+                        sample.timestamp.timestamp_millis(),
+                        activity.activity_type.as_str(),
+                        activity.confidence,
+                    ])?;
+                }
+            }
+        }
+
+        let mut insert_trip = transaction.prepare(
+            "INSERT INTO trips (id, start_timestamp, end_timestamp, first_location_id, last_location_id, activity, points)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        // the trips are consecutive, so the id of each one's first location follows on from the last
+        let mut first = 0;
+        for (id, trip) in split_tracks(locations, TrackSplit::Trip).into_iter().enumerate() {
+            let activity = most_common_activity(trip);
+            insert_trip.execute(params![
+                id as i64,
+                trip[0].timestamp.timestamp_millis(),
+                trip[trip.len() - 1].timestamp.timestamp_millis(),
+                first as i64,
+                (first + trip.len() - 1) as i64,
+                activity.as_ref().map(|a| a.as_str()),
+                trip.len() as i64,
+            ])?;
+            first += trip.len();
+        }
+    }
+    transaction.commit()?;
+
+    connection.execute_batch(INDEXES)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    #[test]
+    fn sqlite_tables() {
+        let locations = crate::fixtures::locations();
+
+        let path = std::env::temp_dir().join(format!("location_history_{}.sqlite", std::process::id()));
+        // written twice, to check an existing file is replaced rather than added to
+        crate::write_sqlite(&path, &locations).unwrap();
+        crate::write_sqlite(&path, &locations).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let (count, timestamp, offset, lat_cell, lon_cell): (i64, i64, i32, i64, i64) = connection
+            .query_row(
+                "SELECT count(*), min(timestamp), min(utc_offset), min(lat_cell), min(lon_cell) FROM locations",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )
+            .unwrap();
+        assert_eq!((count, timestamp, offset), (2, locations[0].timestamp.timestamp_millis(), 36000));
+        assert_eq!((lat_cell, lon_cell), (-3783, 14507));
+
+        let top: (String, f64) = connection
+            .query_row(
+                "SELECT type, latitude FROM activities JOIN locations ON locations.id = location_id ORDER BY confidence DESC",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(top, ("WALKING".to_string(), -37.8224062));
+        let activities: i64 = connection.query_row("SELECT count(*) FROM activities", [], |row| row.get(0)).unwrap();
+        assert_eq!(activities, 2);
+
+        let trip: (i64, i64, Option<String>, i64) = connection
+            .query_row("SELECT first_location_id, last_location_id, activity, points FROM trips", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        assert_eq!(trip, (0, 1, Some("WALKING".to_string()), 2));
+
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+//! Parser for the on-device `Timeline.json` export.
+//!
+//! Since 2024, Google keeps Timeline data on the phone, and the export is a single
+//! `Timeline.json` holding `semanticSegments`, `rawSignals` and a `userLocationProfile`.
+//! Coordinates are written as strings like `"-37.82°, 145.07°"`, rather than E7 integers.
+//!
+//! The raw `position` signals and the `timelinePath` points of the semantic segments are
+//! converted into `Location`s, and `activityRecord` signals are attached as `Activities`
+//! to the most recent position, so the result looks just like a `Records.json`.
+
+use chrono::{DateTime, FixedOffset};
+use serde_derive::Deserialize;
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use struson::reader::{JsonReader, JsonStreamReader};
+
+use crate::{collect_sorted, next_record, parse_timestamp, Activities, Activity, ActivityType, Location, Locations, Result};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SemanticSegment {
+    #[serde(default)]
+    timeline_path: Vec<PathPoint>,
+}
+
+#[derive(Deserialize)]
+struct PathPoint {
+    #[serde(deserialize_with = "parse_lat_lng")]
+    point: (f64, f64),
+    #[serde(deserialize_with = "parse_timestamp")]
+    time: DateTime<FixedOffset>,
+}
+
+// each raw signal holds exactly one of these, wifi scans are ignored
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSignal {
+    position: Option<Position>,
+    activity_record: Option<ActivityRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Position {
+    #[serde(rename = "LatLng", alias = "latLng", deserialize_with = "parse_lat_lng")]
+    lat_lng: (f64, f64),
+    accuracy_meters: Option<f64>,
+    altitude_meters: Option<f64>,
+    speed_meters_per_second: Option<f64>,
+    source: Option<String>,
+    #[serde(deserialize_with = "parse_timestamp")]
+    timestamp: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ActivityRecord {
+    #[serde(default)]
+    probable_activities: Vec<ProbableActivity>,
+    #[serde(deserialize_with = "parse_timestamp")]
+    timestamp: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize)]
+struct ProbableActivity {
+    #[serde(rename = "type")]
+    activity_type: ActivityType,
+    /// probability between 0 and 1
+    confidence: f64,
+}
+
+impl From<PathPoint> for Location {
+    fn from(point: PathPoint) -> Location {
+        Location {
+            timestamp: point.time,
+            latitude: point.point.0,
+            longitude: point.point.1,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Position> for Location {
+    fn from(position: Position) -> Location {
+        Location {
+            timestamp: position.timestamp,
+            latitude: position.lat_lng.0,
+            longitude: position.lat_lng.1,
+            accuracy: position.accuracy_meters.map(|a| a.round() as i32),
+            altitude: position.altitude_meters.map(|a| a.round() as i32),
+            velocity: position.speed_meters_per_second.map(|v| v.round() as i32),
+            source: position.source,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ActivityRecord> for Activities {
+    fn from(record: ActivityRecord) -> Activities {
+        Activities {
+            timestamp: record.timestamp,
+            activities: record
+                .probable_activities
+                .into_iter()
+                .map(|a| Activity {
+                    activity_type: a.activity_type,
+                    // Records.json uses percentages
+                    confidence: (a.confidence * 100.0).round() as i32,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// deserialize an on-device `Timeline.json` export into locations, sorted by timestamp
+pub fn deserialize_timeline(from: &str) -> Result<Locations> {
+    collect_sorted(|tx| read_timeline(JsonStreamReader::new(from.as_bytes()), tx))
+}
+
+/// Reads an on-device `Timeline.json` export and decodes the data on-the-fly,
+/// in the same manner as `deserialize_streaming`.
+///
+/// Locations are sent in file order: all `timelinePath` points first, followed by the
+/// raw `position` signals. Each `activityRecord` is attached to the position before it.
+///
+/// # Arguments
+///
+/// * `from` - The path to the `Timeline.json` file.
+/// * `tx` - The `Sender` channel to send the decoded `Location` objects.
+pub fn deserialize_timeline_streaming(from: PathBuf, tx: Sender<Location>) -> Result<()> {
+    let file = File::open::<PathBuf>(from)?;
+    let reader = BufReader::new(file);
+
+    read_timeline(JsonStreamReader::new(reader), tx)
+}
+
+/// Whether a file is an on-device `Timeline.json` export, whatever it has been renamed to.
+/// Only the top-level keys are read, up to `semanticSegments`, or the `locations` of a `Records.json`.
+pub fn is_timeline(path: &Path) -> Result<bool> {
+    let file = File::open(path)?;
+    read_is_timeline(JsonStreamReader::new(BufReader::new(file)))
+}
+
+fn read_is_timeline<R: Read>(mut json_reader: JsonStreamReader<R>) -> Result<bool> {
+    json_reader.begin_object()?;
+    while json_reader.has_next()? {
+        match json_reader.next_name_owned()?.as_str() {
+            "semanticSegments" => return Ok(true),
+            "locations" => return Ok(false),
+            _ => json_reader.skip_value()?,
+        }
+    }
+    Ok(false)
+}
+
+fn read_timeline<R: Read>(mut json_reader: JsonStreamReader<R>, tx: Sender<Location>) -> Result<()> {
+    json_reader.begin_object()?;
+
+    while json_reader.has_next()? {
+        match json_reader.next_name_owned()?.as_str() {
+            "semanticSegments" => {
+                json_reader.begin_array()?;
+                let mut index = 0;
+                while json_reader.has_next()? {
+                    let segment: SemanticSegment = next_record(&mut json_reader, index)?;
+                    for point in segment.timeline_path {
+                        if tx.send(point.into()).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    index += 1;
+                }
+                json_reader.end_array()?;
+            }
+            "rawSignals" => {
+                // hold on to the latest position, so following activity records can be attached to it
+                let mut pending: Option<Location> = None;
+
+                json_reader.begin_array()?;
+                let mut index = 0;
+                while json_reader.has_next()? {
+                    let signal: RawSignal = next_record(&mut json_reader, index)?;
+                    index += 1;
+
+                    if let Some(position) = signal.position {
+                        if let Some(location) = pending.replace(position.into()) {
+                            if tx.send(location).is_err() {
+                                return Ok(());
+                            }
+                        }
+                    } else if let Some(record) = signal.activity_record {
+                        if let Some(location) = pending.as_mut() {
+                            location
+                                .activities
+                                .get_or_insert_with(Vec::new)
+                                .push(record.into());
+                        }
+                    }
+                }
+                json_reader.end_array()?;
+
+                if let Some(location) = pending {
+                    if tx.send(location).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            _ => json_reader.skip_value()?,
+        }
+    }
+
+    Ok(())
+}
+
+/// parses coordinates in the form `"-37.82°, 145.07°"`, or the older iOS `"geo:-37.82,145.07"`
+fn parse_lat_lng<'de, D>(de: D) -> Result<(f64, f64), D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: String = serde::Deserialize::deserialize(de)?;
+    let s = s.trim().trim_start_matches("geo:");
+
+    let mut parts = s
+        .split(',')
+        .map(|part| part.trim().trim_end_matches('°').parse::<f64>());
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(lat)), Some(Ok(lng)), None) => Ok((lat, lng)),
+        _ => Err(serde::de::Error::custom(format!("Unexpected coordinates: {}", s))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn timeline_export() {
+        let test_data = r#"{
+            "semanticSegments": [ {
+                "startTime": "2024-06-01T00:00:00.000+10:00",
+                "endTime": "2024-06-01T02:00:00.000+10:00",
+                "timelinePath": [ { "point": "-37.8224062°, 145.0703899°", "time": "2024-06-01T00:12:00.000+10:00" } ]
+            }, {
+                "startTime": "2024-06-01T02:00:00.000+10:00",
+                "endTime": "2024-06-01T03:00:00.000+10:00",
+                "visit": { "topCandidate": { "placeId": "abc", "placeLocation": { "latLng": "-37.82°, 145.07°" } } }
+            } ],
+            "rawSignals": [ {
+                "position": { "LatLng": "-37.8224°, 145.0704°", "accuracyMeters": 13, "altitudeMeters": 52.4,
+                              "source": "WIFI", "timestamp": "2024-06-01T00:00:03.000+10:00" }
+            }, {
+                "activityRecord": { "probableActivities": [ { "type": "STILL", "confidence": 0.98 } ],
+                                    "timestamp": "2024-06-01T00:00:04.000+10:00" }
+            }, {
+                "wifiScan": { "deliveryTime": "2024-06-01T00:00:05.000+10:00", "devicesRecords": [] }
+            } ],
+            "userLocationProfile": { "frequentPlaces": [] }
+        }"#;
+
+        let locations = crate::deserialize_timeline(test_data).unwrap();
+        assert_eq!(locations.len(), 2);
+
+        let position = &locations[0];
+        assert_eq!(position.latitude, -37.8224);
+        assert_eq!(position.altitude, Some(52));
+        let activities = position.activities.as_ref().unwrap();
+        assert_eq!(activities[0].activities[0].activity_type, crate::ActivityType::STILL);
+        assert_eq!(activities[0].activities[0].confidence, 98);
+
+        assert_eq!(locations[1].longitude, 145.0703899);
+
+        let is_timeline = |json: &str| super::read_is_timeline(struson::reader::JsonStreamReader::new(json.as_bytes())).unwrap();
+        assert!(is_timeline(test_data));
+        assert!(!is_timeline(r#"{"locations": []}"#));
+    }
+}
@@ -0,0 +1,145 @@
+//! Writing locations back out as a `Records.json`.
+//!
+//! The output mimics the layout of Google's own exports, so that locations read with
+//! `deserialize_lossless` and written back out are byte-for-byte what was read in.
+
+use serde_derive::Serialize;
+use serde_json::ser::Formatter;
+use std::io::{self, Write};
+
+use crate::{Location, Locations, Result};
+
+const INDENT: &[u8] = b"  ";
+
+// Google pretty-prints objects, but keeps arrays inline, e.g:
+//   "locations": [{
+//     "latitudeE7": 500373489,
+//     ...
+//   }, {
+struct TakeoutFormatter {
+    indent: usize,
+    has_value: bool,
+}
+
+impl TakeoutFormatter {
+    fn new() -> Self {
+        TakeoutFormatter {
+            indent: 0,
+            has_value: false,
+        }
+    }
+
+    fn write_indent<W: ?Sized + Write>(&self, writer: &mut W) -> io::Result<()> {
+        for _ in 0..self.indent {
+            writer.write_all(INDENT)?;
+        }
+        Ok(())
+    }
+}
+
+impl Formatter for TakeoutFormatter {
+    fn begin_array_value<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.indent += 1;
+        self.has_value = false;
+        writer.write_all(b"{")
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.indent -= 1;
+
+        if self.has_value {
+            writer.write_all(b"\n")?;
+            self.write_indent(writer)?;
+        }
+        writer.write_all(b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        writer.write_all(if first { b"\n" } else { b",\n" })?;
+        self.write_indent(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b": ")
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct LocationList<'a> {
+    locations: &'a [Location],
+}
+
+/// Writes locations as a Google-compatible `Records.json`, with E7 coordinates.
+pub fn serialize_to_writer<W: Write>(writer: W, locations: &[Location]) -> Result<()> {
+    let mut serializer = serde_json::Serializer::with_formatter(writer, TakeoutFormatter::new());
+    serde::Serialize::serialize(&LocationList { locations }, &mut serializer).map_err(io::Error::from)?;
+    Ok(())
+}
+
+/// serialize locations as a Google-compatible `Records.json`
+pub fn serialize(locations: &Locations) -> Result<String> {
+    let mut out = Vec::new();
+    serialize_to_writer(&mut out, locations)?;
+    // the formatter only ever writes valid UTF-8, but a Serialize impl could write anything
+    Ok(String::from_utf8(out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lossless_round_trip() {
+        let test_data = r#"{
+  "locations": [{
+    "latitudeE7": -378224062,
+    "longitudeE7": 1450703899,
+    "accuracy": 12,
+    "activity": [{
+      "activity": [{
+        "type": "STILL",
+        "confidence": 90
+      }, {
+        "type": "IN_FERRY",
+        "confidence": 10
+      }],
+      "timestamp": "2024-01-01T09:11:58.123Z"
+    }],
+    "source": "WIFI",
+    "deviceTag": -1412907366,
+    "locationMetadata": [{
+      "timestamp": "2024-01-01T09:11:59.001Z",
+      "wifiScan": {
+        "accessPoints": []
+      }
+    }],
+    "timestamp": "2024-01-01T09:12:00Z"
+  }, {
+    "latitudeE7": -378100000,
+    "longitudeE7": 1450800000,
+    "timestamp": "2024-01-01T09:13:00.500Z"
+  }]
+}"#;
+
+        let locations = crate::deserialize_lossless(test_data).unwrap();
+        assert_eq!(crate::serialize(&locations).unwrap(), test_data);
+        let activities = &locations[0].activities.as_ref().unwrap()[0].activities;
+        assert_eq!(activities[1].activity_type, crate::ActivityType::Other("IN_FERRY".to_string()));
+
+        // a filtered subset is still valid Records.json
+        let subset: crate::Locations = locations.into_iter().skip(1).collect();
+        let reparsed = crate::deserialize(&crate::serialize(&subset).unwrap()).unwrap();
+        assert_eq!(reparsed[0].latitude, -37.81);
+    }
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the XML based formats, GPX and KML.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use quick_xml::events::BytesStart;
+use quick_xml::Reader;
+
+// timestamps are written in UTC, e.g. "2024-01-01T09:12:00.123Z"
+pub(crate) fn format_utc(timestamp: DateTime<FixedOffset>) -> String {
+    timestamp.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+// Expects an xsd:dateTime, e.g. "2024-01-01T09:12:00Z". Some loggers leave out the timezone,
+// in which case UTC is assumed, and KML also allows a plain date, e.g. "2024-01-01".
+pub(crate) fn parse_time(s: &str) -> Option<DateTime<FixedOffset>> {
+    let s = s.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Some(timestamp);
+    }
+    if let Ok(timestamp) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(timestamp.and_utc().fixed_offset());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|timestamp| timestamp.and_utc().fixed_offset())
+}
+
+// the byte offset of a start tag the reader has just read. the reader is just past it, and it
+// is `<`, the tag itself, and `>`
+pub(crate) fn start_offset<R>(reader: &Reader<R>, tag: &BytesStart) -> u64 {
+    reader.buffer_position() - tag.len() as u64 - 2
+}
+
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}